@@ -0,0 +1,204 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of `core::alloc::Allocator` , which requires nightly rust.
+
+use crate::{CAlloc, CHeaderAlloc, CMmapAlloc, CPoolAlloc, Counted, UsableSize};
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+/// Converts `ptr` allocated with `layout` into the result of `Allocator` methods.
+#[inline]
+unsafe fn to_slice<A>(alloc: &A, ptr: *mut u8, layout: Layout) -> Result<NonNull<[u8]>, AllocError>
+where
+    A: UsableSize,
+{
+    match NonNull::new(ptr) {
+        None => Err(AllocError),
+        Some(p) => {
+            let len = alloc.usable_size(ptr, layout);
+            Ok(NonNull::slice_from_raw_parts(p, len))
+        }
+    }
+}
+
+/// Fills `slice` with 0 from the `from` th byte to the end.
+///
+/// The slice includes the usable size beyond the requested size, which `Allocator` requires to
+/// be zeroed as well.
+#[inline]
+unsafe fn zero_from(slice: NonNull<[u8]>, from: usize) {
+    if from < slice.len() {
+        let ptr = slice.as_ptr() as *mut u8;
+        ptr::write_bytes(ptr.add(from), 0, slice.len() - from);
+    }
+}
+
+/// Returns a dangling pointer for a zero-sized allocation.
+#[inline]
+fn dangling(layout: Layout) -> NonNull<[u8]> {
+    let ptr = ptr::without_provenance_mut::<u8>(layout.align());
+    NonNull::slice_from_raw_parts(unsafe { NonNull::new_unchecked(ptr) }, 0)
+}
+
+#[inline]
+fn allocate<A>(alloc: &A, layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError>
+where
+    A: GlobalAlloc + UsableSize,
+{
+    if layout.size() == 0 {
+        return Ok(dangling(layout));
+    }
+
+    unsafe {
+        if zeroed {
+            let ret = to_slice(alloc, alloc.alloc_zeroed(layout), layout)?;
+            zero_from(ret, layout.size());
+            Ok(ret)
+        } else {
+            to_slice(alloc, alloc.alloc(layout), layout)
+        }
+    }
+}
+
+#[inline]
+unsafe fn deallocate<A>(alloc: &A, ptr: NonNull<u8>, layout: Layout)
+where
+    A: GlobalAlloc,
+{
+    if layout.size() != 0 {
+        alloc.dealloc(ptr.as_ptr(), layout);
+    }
+}
+
+/// Reallocates `ptr` to `new_layout` .
+/// (`new_layout.size()` can be greater or less than `old_layout.size()` .)
+#[inline]
+unsafe fn reallocate<A>(
+    alloc: &A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError>
+where
+    A: GlobalAlloc + UsableSize,
+{
+    if old_layout.size() == 0 {
+        return allocate(alloc, new_layout, zeroed);
+    }
+
+    if new_layout.size() == 0 {
+        alloc.dealloc(ptr.as_ptr(), old_layout);
+        return Ok(dangling(new_layout));
+    }
+
+    if old_layout.align() == new_layout.align() {
+        let new_ptr = alloc.realloc(ptr.as_ptr(), old_layout, new_layout.size());
+        let ret = to_slice(alloc, new_ptr, new_layout)?;
+        if zeroed {
+            zero_from(ret, old_layout.size());
+        }
+        return Ok(ret);
+    }
+
+    let new = allocate(alloc, new_layout, zeroed)?;
+    let len = old_layout.size().min(new_layout.size());
+    ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr() as *mut u8, len);
+    alloc.dealloc(ptr.as_ptr(), old_layout);
+    Ok(new)
+}
+
+macro_rules! impl_allocator {
+    (impl<$($g:ident),*> $t:ty where $($w:tt)*) => {
+        unsafe impl<$($g),*> Allocator for $t
+        where
+            $($w)*
+        {
+            #[inline]
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                allocate(self, layout, false)
+            }
+
+            #[inline]
+            fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                allocate(self, layout, true)
+            }
+
+            #[inline]
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                deallocate(self, ptr, layout)
+            }
+
+            #[inline]
+            unsafe fn grow(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                debug_assert!(old_layout.size() <= new_layout.size());
+                reallocate(self, ptr, old_layout, new_layout, false)
+            }
+
+            #[inline]
+            unsafe fn grow_zeroed(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                debug_assert!(old_layout.size() <= new_layout.size());
+                reallocate(self, ptr, old_layout, new_layout, true)
+            }
+
+            #[inline]
+            unsafe fn shrink(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                debug_assert!(new_layout.size() <= old_layout.size());
+                reallocate(self, ptr, old_layout, new_layout, false)
+            }
+        }
+    };
+    ($t:ty) => {
+        impl_allocator!(impl<> $t where);
+    };
+}
+
+impl_allocator!(CAlloc);
+impl_allocator!(CPoolAlloc);
+impl_allocator!(CMmapAlloc);
+impl_allocator!(CHeaderAlloc);
+impl_allocator!(impl<A> Counted<A> where A: GlobalAlloc + UsableSize);
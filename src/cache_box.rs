@@ -0,0 +1,364 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Box` like smart pointer allocating memory for cache.
+
+use crate::{CAlloc, TryReserveError};
+use core::alloc::{GlobalAlloc, Layout};
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+/// `CacheBox` is same to `std::boxed::Box` except for it allocates memory through `A` ,
+/// so that the memory is charged to the cache.
+///
+/// ```
+/// use mouse_cache_alloc::{cache_size, CacheBox};
+///
+/// let b: CacheBox<[u8; 1024]> = CacheBox::new([0; 1024]);
+/// assert!(1024 <= cache_size());
+/// assert_eq!(0, b[1023]);
+/// ```
+pub struct CacheBox<T, A = CAlloc>
+where
+    A: GlobalAlloc,
+{
+    ptr: NonNull<T>,
+    alloc: A,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T, A> Send for CacheBox<T, A>
+where
+    T: Send,
+    A: GlobalAlloc + Send,
+{
+}
+
+unsafe impl<T, A> Sync for CacheBox<T, A>
+where
+    T: Sync,
+    A: GlobalAlloc + Sync,
+{
+}
+
+impl<T, A> Drop for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.free();
+        }
+    }
+}
+
+impl<T, A> CacheBox<T, A>
+where
+    A: GlobalAlloc + Default,
+{
+    /// Allocates memory and moves `value` into it.
+    ///
+    /// Calls `std::alloc::handle_alloc_error` if failed to allocate memory (e.g. the cache limit
+    /// is exceeded.)
+    /// Use [`try_new`] not to abort.
+    ///
+    /// [`try_new`]: #method.try_new
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self::new_in(value, A::default())
+    }
+
+    /// Same to [`new`] except for this method returns an error if failed to allocate memory.
+    ///
+    /// `value` is dropped on error.
+    ///
+    /// [`new`]: #method.new
+    #[inline]
+    pub fn try_new(value: T) -> Result<Self, TryReserveError> {
+        Self::try_new_in(value, A::default())
+    }
+}
+
+impl<T, A> CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    /// Allocates memory through `alloc` and moves `value` into it.
+    ///
+    /// Calls `std::alloc::handle_alloc_error` if failed to allocate memory (e.g. the cache limit
+    /// is exceeded.)
+    /// Use [`try_new_in`] not to abort.
+    ///
+    /// [`try_new_in`]: #method.try_new_in
+    pub fn new_in(value: T, alloc: A) -> Self {
+        match Self::try_new_in(value, alloc) {
+            Ok(ret) => ret,
+            Err(e) => e.handle(),
+        }
+    }
+
+    /// Same to [`new_in`] except for this method returns an error if failed to allocate
+    /// memory.
+    ///
+    /// `value` is dropped on error.
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CPoolAlloc, CacheBox, CachePool};
+    ///
+    /// static POOL: CachePool = CachePool::new();
+    /// POOL.set_limit(1024);
+    ///
+    /// let alloc = CPoolAlloc::new(&POOL);
+    /// assert!(CacheBox::try_new_in([0_u8; 100], alloc).is_ok());
+    /// assert!(CacheBox::try_new_in([0_u8; 2048], alloc).is_err());
+    /// ```
+    ///
+    /// [`new_in`]: #method.new_in
+    pub fn try_new_in(value: T, alloc: A) -> Result<Self, TryReserveError> {
+        let layout = Layout::new::<T>();
+
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            match NonNull::new(unsafe { alloc.alloc(layout) } as *mut T) {
+                None => return Err(TryReserveError::AllocError(layout)),
+                Some(p) => p,
+            }
+        };
+
+        unsafe { ptr::write(ptr.as_ptr(), value) };
+
+        Ok(Self {
+            ptr,
+            alloc,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns a reference to the allocator.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Consumes `this` and returns the wrapped value.
+    pub fn into_inner(this: Self) -> T {
+        let this = mem::ManuallyDrop::new(this);
+
+        unsafe {
+            let ret = ptr::read(this.ptr.as_ptr());
+            this.free();
+            ptr::read(&this.alloc);
+            ret
+        }
+    }
+
+    /// Deallocates the memory without dropping the value.
+    #[inline]
+    unsafe fn free(&self) {
+        let layout = Layout::new::<T>();
+        if layout.size() != 0 {
+            self.alloc.dealloc(self.ptr.as_ptr() as *mut u8, layout);
+        }
+    }
+}
+
+impl<T, A> Deref for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A> DerefMut for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T, A> AsRef<T> for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T, A> AsMut<T> for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T, A> Borrow<T> for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T, A> BorrowMut<T> for CacheBox<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T, A> Clone for CacheBox<T, A>
+where
+    T: Clone,
+    A: GlobalAlloc + Clone,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new_in(T::clone(self), self.alloc.clone())
+    }
+}
+
+impl<T, A> Default for CacheBox<T, A>
+where
+    T: Default,
+    A: GlobalAlloc + Default,
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T, A> From<T> for CacheBox<T, A>
+where
+    A: GlobalAlloc + Default,
+{
+    #[inline]
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T, A> fmt::Debug for CacheBox<T, A>
+where
+    T: fmt::Debug,
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, A> fmt::Display for CacheBox<T, A>
+where
+    T: fmt::Display,
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T, A> PartialEq for CacheBox<T, A>
+where
+    T: PartialEq,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        T::eq(self, other)
+    }
+}
+
+impl<T, A> Eq for CacheBox<T, A>
+where
+    T: Eq,
+    A: GlobalAlloc,
+{
+}
+
+impl<T, A> PartialOrd for CacheBox<T, A>
+where
+    T: PartialOrd,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        T::partial_cmp(self, other)
+    }
+}
+
+impl<T, A> Ord for CacheBox<T, A>
+where
+    T: Ord,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        T::cmp(self, other)
+    }
+}
+
+impl<T, A> Hash for CacheBox<T, A>
+where
+    T: Hash,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        T::hash(self, state);
+    }
+}
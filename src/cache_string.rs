@@ -0,0 +1,463 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `String` like collection allocating memory for cache.
+
+use crate::{CAlloc, CacheVec, TryReserveError};
+use core::alloc::GlobalAlloc;
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+use core::ops::{Deref, DerefMut};
+use core::str;
+
+/// `CacheString` is same to `std::string::String` except for it allocates memory through `A` ,
+/// so that the memory is charged to the cache.
+///
+/// ```
+/// use mouse_cache_alloc::CacheString;
+///
+/// let mut s: CacheString = CacheString::from("foo");
+/// s.push_str("bar");
+/// s.push('!');
+///
+/// assert_eq!("foobar!", s);
+/// ```
+pub struct CacheString<A = CAlloc>
+where
+    A: GlobalAlloc,
+{
+    vec: CacheVec<u8, A>,
+}
+
+impl<A> Default for CacheString<A>
+where
+    A: GlobalAlloc + Default,
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> CacheString<A>
+where
+    A: GlobalAlloc + Default,
+{
+    /// Creates a new empty instance without allocating memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self::new_in(A::default())
+    }
+
+    /// Creates a new empty instance with at least `capacity` bytes capacity.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, A::default())
+    }
+}
+
+impl<A> CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    /// Creates a new empty instance allocating memory through `alloc` later.
+    #[inline]
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            vec: CacheVec::new_in(alloc),
+        }
+    }
+
+    /// Creates a new empty instance with at least `capacity` bytes capacity allocating memory
+    /// through `alloc` .
+    #[inline]
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self {
+            vec: CacheVec::with_capacity_in(capacity, alloc),
+        }
+    }
+
+    /// Returns a reference to the allocator.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        self.vec.allocator()
+    }
+
+    /// Returns the byte length.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    /// Returns `true` if `self` is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    /// Returns the byte size `self` can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+
+    /// Extracts a string slice containing the entire string.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.vec) }
+    }
+
+    /// Extracts a mutable string slice containing the entire string.
+    #[inline]
+    pub fn as_mut_str(&mut self) -> &mut str {
+        unsafe { str::from_utf8_unchecked_mut(&mut self.vec) }
+    }
+
+    /// Returns the bytes of `self` .
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.vec
+    }
+
+    /// Reserves capacity for at least `additional` bytes more.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional);
+    }
+
+    /// Same to [`reserve`] except for this method returns an error instead of panicking or
+    /// calling `std::alloc::handle_alloc_error` .
+    ///
+    /// [`reserve`]: #method.reserve
+    #[inline]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.vec.try_reserve(additional)
+    }
+
+    /// Shrinks the capacity as much as possible.
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.vec.shrink_to_fit();
+    }
+
+    /// Appends `s` to the back.
+    #[inline]
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_slice(s.as_bytes());
+    }
+
+    /// Same to [`push_str`] except for this method returns an error if failed to allocate
+    /// memory.
+    ///
+    /// `self` is not changed on error.
+    ///
+    /// [`push_str`]: #method.push_str
+    #[inline]
+    pub fn try_push_str(&mut self, s: &str) -> Result<(), TryReserveError> {
+        self.vec.try_reserve(s.len())?;
+        self.vec.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    /// Appends `c` to the back.
+    #[inline]
+    pub fn push(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.push_str(c.encode_utf8(&mut buf));
+    }
+
+    /// Removes the last character and returns it, or `None` if `self` is empty.
+    #[inline]
+    pub fn pop(&mut self) -> Option<char> {
+        let c = self.as_str().chars().next_back()?;
+        self.vec.truncate(self.len() - c.len_utf8());
+        Some(c)
+    }
+
+    /// Shortens `self` to `new_len` bytes.
+    ///
+    /// Does nothing if `new_len` is greater than or equal to the current length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    #[inline]
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len() {
+            assert!(self.as_str().is_char_boundary(new_len));
+            self.vec.truncate(new_len);
+        }
+    }
+
+    /// Makes `self` empty. The capacity is not changed.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+}
+
+impl<A> Deref for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<A> DerefMut for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut str {
+        self.as_mut_str()
+    }
+}
+
+impl<A> AsRef<str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl<A> AsRef<[u8]> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<A> Borrow<str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl<A> BorrowMut<str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl<A> Clone for CacheString<A>
+where
+    A: GlobalAlloc + Clone,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            vec: self.vec.clone(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<A> fmt::Display for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<A> fmt::Write for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl<A, B> PartialEq<CacheString<B>> for CacheString<A>
+where
+    A: GlobalAlloc,
+    B: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &CacheString<B>) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<A> PartialEq<str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<A> PartialEq<&str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<A> PartialEq<CacheString<A>> for &str
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &CacheString<A>) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl<A> Eq for CacheString<A> where A: GlobalAlloc {}
+
+impl<A> PartialOrd for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<A> Ord for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl<A> Hash for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl<A> Extend<char> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+
+        for c in iter {
+            self.push(c);
+        }
+    }
+}
+
+impl<'a, A> Extend<&'a str> for CacheString<A>
+where
+    A: GlobalAlloc,
+{
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for s in iter {
+            self.push_str(s);
+        }
+    }
+}
+
+impl<A> FromIterator<char> for CacheString<A>
+where
+    A: GlobalAlloc + Default,
+{
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> Self {
+        let mut ret = Self::new();
+        ret.extend(iter);
+        ret
+    }
+}
+
+impl<'a, A> FromIterator<&'a str> for CacheString<A>
+where
+    A: GlobalAlloc + Default,
+{
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut ret = Self::new();
+        ret.extend(iter);
+        ret
+    }
+}
+
+impl<A> From<&str> for CacheString<A>
+where
+    A: GlobalAlloc + Default,
+{
+    #[inline]
+    fn from(s: &str) -> Self {
+        let mut ret = Self::with_capacity(s.len());
+        ret.push_str(s);
+        ret
+    }
+}
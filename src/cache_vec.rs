@@ -0,0 +1,1064 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Vec` like collection allocating memory for cache.
+
+use crate::CAlloc;
+use core::alloc::{GlobalAlloc, Layout};
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Bound, Deref, DerefMut, RangeBounds};
+use core::ptr::{self, NonNull};
+use core::slice;
+
+/// Error of the fallible allocation methods like `CacheVec::try_reserve` .
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TryReserveError {
+    /// The required capacity overflows.
+    CapacityOverflow,
+    /// Failed to allocate memory of the layout. (e.g. the cache limit is exceeded.)
+    AllocError(Layout),
+}
+
+impl TryReserveError {
+    /// Panics or calls `std::alloc::handle_alloc_error` as the infallible methods do.
+    #[inline]
+    pub(crate) fn handle(self) -> ! {
+        match self {
+            Self::CapacityOverflow => panic!("capacity overflow"),
+            Self::AllocError(layout) => std::alloc::handle_alloc_error(layout),
+        }
+    }
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::CapacityOverflow => f.write_str("failed to reserve memory: capacity overflow"),
+            Self::AllocError(layout) => write!(
+                f,
+                "failed to reserve memory: failed to allocate {} bytes",
+                layout.size()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TryReserveError {}
+
+/// `CacheVec` is same to `std::vec::Vec` except for it allocates memory through `A` ,
+/// so that the memory is charged to the cache.
+///
+/// ```
+/// use mouse_cache_alloc::{cache_size, CacheVec};
+///
+/// let mut v: CacheVec<u64> = (0..100).collect();
+/// v.push(100);
+///
+/// assert_eq!(101, v.len());
+/// assert_eq!(5050, v.iter().sum::<u64>());
+/// assert!(101 * 8 <= cache_size());
+/// ```
+pub struct CacheVec<T, A = CAlloc>
+where
+    A: GlobalAlloc,
+{
+    ptr: NonNull<T>,
+    cap: usize,
+    len: usize,
+    alloc: A,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T, A> Send for CacheVec<T, A>
+where
+    T: Send,
+    A: GlobalAlloc + Send,
+{
+}
+
+unsafe impl<T, A> Sync for CacheVec<T, A>
+where
+    T: Sync,
+    A: GlobalAlloc + Sync,
+{
+}
+
+impl<T, A> Drop for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    fn drop(&mut self) {
+        self.clear();
+
+        if 0 < self.cap && 0 < mem::size_of::<T>() {
+            unsafe {
+                self.alloc
+                    .dealloc(self.ptr.as_ptr() as *mut u8, self.layout())
+            };
+        }
+    }
+}
+
+impl<T, A> Default for CacheVec<T, A>
+where
+    A: GlobalAlloc + Default,
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A> CacheVec<T, A>
+where
+    A: GlobalAlloc + Default,
+{
+    /// Creates a new empty instance without allocating memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self::new_in(A::default())
+    }
+
+    /// Creates a new empty instance with at least `capacity` capacity.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, A::default())
+    }
+}
+
+impl<T, A> CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    /// Creates a new empty instance allocating memory through `alloc` later.
+    #[inline]
+    pub fn new_in(alloc: A) -> Self {
+        let cap = if mem::size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            0
+        };
+
+        Self {
+            ptr: NonNull::dangling(),
+            cap,
+            len: 0,
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a new empty instance with at least `capacity` capacity allocating memory through
+    /// `alloc` .
+    #[inline]
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        let mut ret = Self::new_in(alloc);
+        ret.reserve(capacity);
+        ret
+    }
+
+    /// Returns a reference to the allocator.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns the number of the elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if `self` has no element.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of the elements `self` can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns a raw pointer to the buffer.
+    #[inline]
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns a raw mutable pointer to the buffer.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Extracts a slice containing the entire elements.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Extracts a mutable slice containing the entire elements.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Forces the length to `new_len` .
+    ///
+    /// # Safety
+    ///
+    /// `new_len` must be less than or equal to the capacity, and the elements up to `new_len`
+    /// must be initialized.
+    #[inline]
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap);
+        self.len = new_len;
+    }
+
+    /// Reserves capacity for at least `additional` more elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new capacity overflows.
+    ///
+    /// Calls `std::alloc::handle_alloc_error` if failed to allocate memory (e.g. the cache limit
+    /// is exceeded.)
+    /// Use [`try_reserve`] not to abort.
+    ///
+    /// [`try_reserve`]: #method.try_reserve
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        if let Err(e) = self.try_reserve(additional) {
+            e.handle();
+        }
+    }
+
+    /// Reserves the minimum capacity for exactly `additional` more elements.
+    ///
+    /// See [`reserve`] for details.
+    ///
+    /// [`reserve`]: #method.reserve
+    #[inline]
+    pub fn reserve_exact(&mut self, additional: usize) {
+        if let Err(e) = self.try_reserve_exact(additional) {
+            e.handle();
+        }
+    }
+
+    /// Same to [`reserve`] except for this method returns an error instead of panicking or
+    /// calling `std::alloc::handle_alloc_error` .
+    ///
+    /// `self` is not changed on error.
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CPoolAlloc, CacheVec, CachePool, TryReserveError};
+    ///
+    /// static POOL: CachePool = CachePool::new();
+    /// POOL.set_limit(1024);
+    ///
+    /// let mut v: CacheVec<u8, CPoolAlloc> = CacheVec::new_in(CPoolAlloc::new(&POOL));
+    /// assert!(v.try_reserve(100).is_ok());
+    /// assert!(matches!(v.try_reserve(2048), Err(TryReserveError::AllocError(_))));
+    /// assert!(100 <= v.capacity());
+    /// ```
+    ///
+    /// [`reserve`]: #method.reserve
+    #[inline]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if required <= self.cap {
+            return Ok(());
+        }
+
+        let new_cap = required.max(self.cap * 2).max(Self::MIN_CAPACITY);
+        self.try_set_capacity(new_cap)
+    }
+
+    /// Same to [`reserve_exact`] except for this method returns an error instead of panicking
+    /// or calling `std::alloc::handle_alloc_error` .
+    ///
+    /// `self` is not changed on error.
+    ///
+    /// [`reserve_exact`]: #method.reserve_exact
+    #[inline]
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if self.cap < required {
+            self.try_set_capacity(required)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Shrinks the capacity as much as possible.
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        if mem::size_of::<T>() != 0 && self.len < self.cap {
+            self.set_capacity(self.len);
+        }
+    }
+
+    /// Appends `value` to the back.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+    }
+
+    /// Same to [`push`] except for this method returns an error if failed to allocate memory.
+    ///
+    /// `value` is dropped on error.
+    ///
+    /// [`push`]: #method.push
+    #[inline]
+    pub fn try_push(&mut self, value: T) -> Result<(), TryReserveError> {
+        if self.len == self.cap {
+            self.try_reserve(1)?;
+        }
+
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last element and returns it, or `None` if `self` is empty.
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            unsafe { Some(ptr::read(self.ptr.as_ptr().add(self.len))) }
+        }
+    }
+
+    /// Inserts `value` at position `index` , shifting all the elements after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the length.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len, "insertion index is out of bounds");
+
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+
+        unsafe {
+            let p = self.ptr.as_ptr().add(index);
+            ptr::copy(p, p.add(1), self.len - index);
+            ptr::write(p, value);
+        }
+        self.len += 1;
+    }
+
+    /// Removes and returns the element at position `index` , shifting all the elements after
+    /// it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index is out of bounds");
+
+        unsafe {
+            let p = self.ptr.as_ptr().add(index);
+            let ret = ptr::read(p);
+            ptr::copy(p.add(1), p, self.len - index - 1);
+            self.len -= 1;
+            ret
+        }
+    }
+
+    /// Removes and returns the element at position `index` , replacing it with the last
+    /// element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "swap_remove index is out of bounds");
+
+        unsafe {
+            let base = self.ptr.as_ptr();
+            let ret = ptr::read(base.add(index));
+            self.len -= 1;
+            ptr::copy(base.add(self.len), base.add(index), 1);
+            ret
+        }
+    }
+
+    /// Shortens `self` to `len` elements and drops the rest.
+    ///
+    /// Does nothing if `len` is greater than or equal to the current length.
+    pub fn truncate(&mut self, len: usize) {
+        if self.len <= len {
+            return;
+        }
+
+        let old_len = self.len;
+        // Update the length first in case of panic in `drop` .
+        self.len = len;
+        unsafe {
+            let tail = slice::from_raw_parts_mut(self.ptr.as_ptr().add(len), old_len - len);
+            ptr::drop_in_place(tail);
+        }
+    }
+
+    /// Drops all the elements. The capacity is not changed.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Retains only the elements for which `f` returns `true` .
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let len = self.len;
+        let mut kept = 0;
+
+        // Set the length to 0 in case of panic; the elements are leaked then.
+        self.len = 0;
+
+        unsafe {
+            let base = self.ptr.as_ptr();
+            for i in 0..len {
+                let p = base.add(i);
+                if f(&*p) {
+                    if kept != i {
+                        ptr::copy_nonoverlapping(p, base.add(kept), 1);
+                    }
+                    kept += 1;
+                } else {
+                    ptr::drop_in_place(p);
+                }
+            }
+        }
+
+        self.len = kept;
+    }
+
+    /// Removes the elements in `range` and returns them as an iterator.
+    ///
+    /// The elements are removed even if the iterator is not consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of `range` is greater than the end, or if the end is greater than
+    /// the length.
+    pub fn drain<R>(&mut self, range: R) -> Drain<'_, T, A>
+    where
+        R: RangeBounds<usize>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).expect("drain range overflow"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n.checked_add(1).expect("drain range overflow"),
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.len,
+        };
+        assert!(start <= end, "drain range start is greater than the end");
+        assert!(end <= self.len, "drain range end is out of bounds");
+
+        let tail_len = self.len - end;
+
+        // Set the length to `start` in case `Drain` is leaked; the rest elements are leaked
+        // then.
+        self.len = start;
+
+        Drain {
+            vec: self,
+            front: start,
+            back: end,
+            tail_start: end,
+            tail_len,
+        }
+    }
+
+    const MIN_CAPACITY: usize = if mem::size_of::<T>() == 1 { 8 } else { 4 };
+
+    /// Returns the layout of the current buffer.
+    #[inline]
+    fn layout(&self) -> Layout {
+        unsafe {
+            Layout::from_size_align_unchecked(mem::size_of::<T>() * self.cap, mem::align_of::<T>())
+        }
+    }
+
+    /// Reallocates the buffer to hold `cap` elements.
+    #[inline]
+    fn set_capacity(&mut self, cap: usize) {
+        if let Err(e) = self.try_set_capacity(cap) {
+            e.handle();
+        }
+    }
+
+    /// Reallocates the buffer to hold `cap` elements, or returns an error without changing
+    /// anything.
+    fn try_set_capacity(&mut self, cap: usize) -> Result<(), TryReserveError> {
+        debug_assert!(self.len <= cap);
+
+        if mem::size_of::<T>() == 0 {
+            return if cap <= self.cap {
+                Ok(())
+            } else {
+                Err(TryReserveError::CapacityOverflow)
+            };
+        }
+
+        if cap == 0 {
+            unsafe {
+                self.alloc
+                    .dealloc(self.ptr.as_ptr() as *mut u8, self.layout())
+            };
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+            return Ok(());
+        }
+
+        let new_layout = Layout::array::<T>(cap).map_err(|_| TryReserveError::CapacityOverflow)?;
+        let ptr = unsafe {
+            if self.cap == 0 {
+                self.alloc.alloc(new_layout)
+            } else {
+                self.alloc.realloc(
+                    self.ptr.as_ptr() as *mut u8,
+                    self.layout(),
+                    new_layout.size(),
+                )
+            }
+        };
+
+        match NonNull::new(ptr as *mut T) {
+            None => Err(TryReserveError::AllocError(new_layout)),
+            Some(p) => {
+                self.ptr = p;
+                self.cap = cap;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T, A> CacheVec<T, A>
+where
+    T: Clone,
+    A: GlobalAlloc,
+{
+    /// Clones and appends all the elements in `other` .
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        for t in other {
+            self.push(t.clone());
+        }
+    }
+
+    /// Resizes `self` to `new_len` filling the new elements with `value` .
+    pub fn resize(&mut self, new_len: usize, value: T) {
+        if new_len <= self.len {
+            self.truncate(new_len);
+        } else {
+            self.reserve(new_len - self.len);
+            while self.len < new_len {
+                self.push(value.clone());
+            }
+        }
+    }
+}
+
+impl<T, A> Deref for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, A> DerefMut for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T, A> AsRef<[T]> for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T, A> AsMut<[T]> for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T, A> Borrow<[T]> for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T, A> BorrowMut<[T]> for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T, A> Clone for CacheVec<T, A>
+where
+    T: Clone,
+    A: GlobalAlloc + Clone,
+{
+    fn clone(&self) -> Self {
+        let mut ret = Self::with_capacity_in(self.len, self.alloc.clone());
+        ret.extend_from_slice(self);
+        ret
+    }
+}
+
+impl<T, A> fmt::Debug for CacheVec<T, A>
+where
+    T: fmt::Debug,
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<T, A, B> PartialEq<CacheVec<T, B>> for CacheVec<T, A>
+where
+    T: PartialEq,
+    A: GlobalAlloc,
+    B: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &CacheVec<T, B>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T, A> PartialEq<[T]> for CacheVec<T, A>
+where
+    T: PartialEq,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn eq(&self, other: &[T]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<T, A> Eq for CacheVec<T, A>
+where
+    T: Eq,
+    A: GlobalAlloc,
+{
+}
+
+impl<T, A> PartialOrd for CacheVec<T, A>
+where
+    T: PartialOrd,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.as_slice().partial_cmp(other.as_slice())
+    }
+}
+
+impl<T, A> Ord for CacheVec<T, A>
+where
+    T: Ord,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl<T, A> Hash for CacheVec<T, A>
+where
+    T: Hash,
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state);
+    }
+}
+
+impl<T, A> Extend<T> for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+
+        for t in iter {
+            self.push(t);
+        }
+    }
+}
+
+impl<'a, T, A> Extend<&'a T> for CacheVec<T, A>
+where
+    T: 'a + Copy,
+    A: GlobalAlloc,
+{
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T, A> FromIterator<T> for CacheVec<T, A>
+where
+    A: GlobalAlloc + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut ret = Self::new();
+        ret.extend(iter);
+        ret
+    }
+}
+
+impl<T, A> From<&[T]> for CacheVec<T, A>
+where
+    T: Clone,
+    A: GlobalAlloc + Default,
+{
+    fn from(s: &[T]) -> Self {
+        let mut ret = Self::new();
+        ret.extend_from_slice(s);
+        ret
+    }
+}
+
+impl<'a, T, A> IntoIterator for &'a CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, A> IntoIterator for &'a mut CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, A> IntoIterator for CacheVec<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Item = T;
+    type IntoIter = IntoIter<T, A>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        let this = mem::ManuallyDrop::new(self);
+
+        IntoIter {
+            ptr: this.ptr,
+            cap: this.cap,
+            front: 0,
+            back: this.len,
+            alloc: unsafe { ptr::read(&this.alloc) },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Iterator moving the elements out of [`CacheVec`] .
+///
+/// The elements not consumed are dropped with the iterator.
+///
+/// [`CacheVec`]: struct.CacheVec.html
+pub struct IntoIter<T, A = CAlloc>
+where
+    A: GlobalAlloc,
+{
+    ptr: NonNull<T>,
+    cap: usize,
+    front: usize,
+    back: usize,
+    alloc: A,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T, A> Send for IntoIter<T, A>
+where
+    T: Send,
+    A: GlobalAlloc + Send,
+{
+}
+
+unsafe impl<T, A> Sync for IntoIter<T, A>
+where
+    T: Sync,
+    A: GlobalAlloc + Sync,
+{
+}
+
+impl<T, A> Drop for IntoIter<T, A>
+where
+    A: GlobalAlloc,
+{
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.as_mut_slice());
+
+            if 0 < self.cap && 0 < mem::size_of::<T>() {
+                let layout = Layout::from_size_align_unchecked(
+                    mem::size_of::<T>() * self.cap,
+                    mem::align_of::<T>(),
+                );
+                self.alloc.dealloc(self.ptr.as_ptr() as *mut u8, layout);
+            }
+        }
+    }
+}
+
+impl<T, A> IntoIter<T, A>
+where
+    A: GlobalAlloc,
+{
+    /// Returns the elements not consumed yet as a slice.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(self.front), self.back - self.front) }
+    }
+
+    /// Returns the elements not consumed yet as a mutable slice.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe {
+            slice::from_raw_parts_mut(self.ptr.as_ptr().add(self.front), self.back - self.front)
+        }
+    }
+}
+
+impl<T, A> Iterator for IntoIter<T, A>
+where
+    A: GlobalAlloc,
+{
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.front == self.back {
+            None
+        } else {
+            self.front += 1;
+            unsafe { Some(ptr::read(self.ptr.as_ptr().add(self.front - 1))) }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<T, A> DoubleEndedIterator for IntoIter<T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        if self.front == self.back {
+            None
+        } else {
+            self.back -= 1;
+            unsafe { Some(ptr::read(self.ptr.as_ptr().add(self.back))) }
+        }
+    }
+}
+
+impl<T, A> ExactSizeIterator for IntoIter<T, A> where A: GlobalAlloc {}
+
+impl<T, A> FusedIterator for IntoIter<T, A> where A: GlobalAlloc {}
+
+impl<T, A> fmt::Debug for IntoIter<T, A>
+where
+    T: fmt::Debug,
+    A: GlobalAlloc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("IntoIter").field(&self.as_slice()).finish()
+    }
+}
+
+/// Iterator removing a range of the elements from [`CacheVec`] , which `CacheVec::drain`
+/// returns.
+///
+/// The elements not consumed are dropped with the iterator, and then the elements after the
+/// range are moved to fill the gap.
+///
+/// [`CacheVec`]: struct.CacheVec.html
+pub struct Drain<'a, T, A = CAlloc>
+where
+    A: GlobalAlloc,
+{
+    vec: &'a mut CacheVec<T, A>,
+    front: usize,
+    back: usize,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<T, A> Drop for Drain<'_, T, A>
+where
+    A: GlobalAlloc,
+{
+    fn drop(&mut self) {
+        unsafe {
+            let base = self.vec.ptr.as_ptr();
+
+            // The tail is leaked if `drop` panics.
+            let rest = slice::from_raw_parts_mut(base.add(self.front), self.back - self.front);
+            self.front = self.back;
+            ptr::drop_in_place(rest);
+
+            let len = self.vec.len;
+            if len != self.tail_start {
+                ptr::copy(base.add(self.tail_start), base.add(len), self.tail_len);
+            }
+            self.vec.len = len + self.tail_len;
+        }
+    }
+}
+
+impl<T, A> Iterator for Drain<'_, T, A>
+where
+    A: GlobalAlloc,
+{
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.front == self.back {
+            None
+        } else {
+            self.front += 1;
+            unsafe { Some(ptr::read(self.vec.ptr.as_ptr().add(self.front - 1))) }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<T, A> DoubleEndedIterator for Drain<'_, T, A>
+where
+    A: GlobalAlloc,
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        if self.front == self.back {
+            None
+        } else {
+            self.back -= 1;
+            unsafe { Some(ptr::read(self.vec.ptr.as_ptr().add(self.back))) }
+        }
+    }
+}
+
+impl<T, A> ExactSizeIterator for Drain<'_, T, A> where A: GlobalAlloc {}
+
+impl<T, A> FusedIterator for Drain<'_, T, A> where A: GlobalAlloc {}
@@ -0,0 +1,593 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counting layer charging the memory allocated by any `GlobalAlloc` to a pool.
+
+use crate::{page_round, CAlloc, CHeaderAlloc, CMmapAlloc, CPoolAlloc, CachePool};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use std::alloc::System;
+
+/// `UsableSize` tells the byte size actually allocated for each allocation.
+///
+/// [`Counted`] charges the pool with the value of this trait.
+///
+/// [`Counted`]: struct.Counted.html
+pub trait UsableSize {
+    /// Returns the byte size actually allocated for `ptr` , which `self` has allocated with
+    /// `layout` .
+    ///
+    /// The result must be greater than or equal to `layout.size()` , and must be the same while
+    /// `ptr` is alive.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `self` allocated with `layout` , and must not have been deallocated
+    /// yet.
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize;
+}
+
+impl UsableSize for System {
+    /// Returns `malloc_usable_size(ptr)` , or `layout.size()` under feature `layout_size` .
+    ///
+    /// See [`allocating_size`] for details.
+    ///
+    /// [`allocating_size`]: fn.allocating_size.html
+    #[inline]
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        #[cfg(feature = "layout_size")]
+        {
+            let _ = ptr;
+            layout.size()
+        }
+        #[cfg(not(feature = "layout_size"))]
+        {
+            let _ = layout;
+            crate::allocating_size(ptr)
+        }
+    }
+}
+
+impl UsableSize for mmap_allocator::MmapAllocator {
+    /// Returns `layout.size()` rounded up to the multiple of the OS page size.
+    #[inline]
+    unsafe fn usable_size(&self, _ptr: *mut u8, layout: Layout) -> usize {
+        page_round(layout.size())
+    }
+}
+
+impl UsableSize for CAlloc {
+    #[inline]
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        heap_size(ptr, layout)
+    }
+}
+
+impl UsableSize for CPoolAlloc {
+    #[inline]
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        heap_size(ptr, layout)
+    }
+}
+
+impl UsableSize for CHeaderAlloc {
+    #[inline]
+    unsafe fn usable_size(&self, _ptr: *mut u8, layout: Layout) -> usize {
+        layout.size()
+    }
+}
+
+impl UsableSize for CMmapAlloc {
+    #[inline]
+    unsafe fn usable_size(&self, _ptr: *mut u8, layout: Layout) -> usize {
+        match self.slab_class(layout) {
+            Some(class) => crate::slab::class_size(class),
+            // The object is aligned to the guard page.
+            None if self.guarded() => layout.size(),
+            None => self.mapping_size(layout.size()),
+        }
+    }
+}
+
+/// `Counted` is a wrapper of `GlobalAlloc` to charge the allocating memory to a pool.
+///
+/// `Counted` has the same features as [`CPoolAlloc`] ; i.e. it respects the limit and the
+/// watermarks of the pool, calls the shrinkers when failed to allocate memory, and updates the
+/// statistics. The charged size is what [`UsableSize`] returns.
+///
+/// ```
+/// use mouse_cache_alloc::{CachePool, Counted};
+/// use std::alloc::{GlobalAlloc, Layout, System};
+///
+/// static POOL: CachePool = CachePool::new();
+/// static ALLOC: Counted<System> = Counted::new(System, &POOL);
+///
+/// let layout = Layout::new::<[u8; 100]>();
+/// unsafe {
+///     let ptr = ALLOC.alloc(layout);
+///     assert!(100 <= POOL.cache_size());
+///
+///     ALLOC.dealloc(ptr, layout);
+///     assert_eq!(0, POOL.cache_size());
+/// }
+/// ```
+///
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+/// [`UsableSize`]: trait.UsableSize.html
+#[derive(Clone, Copy)]
+pub struct Counted<A> {
+    alloc: A,
+    pool: &'static CachePool,
+}
+
+impl<A> Counted<A> {
+    /// Creates a new instance allocating memory through `alloc` and charging it to `pool` .
+    #[inline]
+    pub const fn new(alloc: A, pool: &'static CachePool) -> Self {
+        Self { alloc, pool }
+    }
+
+    /// Returns the pool which `self` charges to.
+    #[inline]
+    pub fn pool(&self) -> &'static CachePool {
+        self.pool
+    }
+
+    /// Returns a reference to the backend allocator.
+    #[inline]
+    pub fn inner(&self) -> &A {
+        &self.alloc
+    }
+}
+
+impl<A> Counted<A>
+where
+    A: GlobalAlloc + UsableSize,
+{
+    /// Same to `alloc` except for this method does not call the shrinkers.
+    #[inline]
+    unsafe fn alloc_once(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc.alloc(layout);
+        self.charge(ptr, layout)
+    }
+
+    /// Same to `alloc_zeroed` except for this method does not call the shrinkers.
+    #[inline]
+    unsafe fn alloc_zeroed_once(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc.alloc_zeroed(layout);
+        self.charge(ptr, layout)
+    }
+
+    /// Charges `ptr` allocated with `layout` to the pool and returns `ptr` .
+    /// If the limit is exceeded, deallocates `ptr` and returns null.
+    #[inline]
+    unsafe fn charge(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        if !ptr.is_null() {
+            let size = self.alloc.usable_size(ptr, layout);
+            if !self.pool.try_increase_size(size) {
+                self.alloc.dealloc(ptr, layout);
+                return ptr::null_mut();
+            }
+        }
+
+        ptr
+    }
+
+    /// Registers `ptr` allocated with `layout` unless `ptr` is null.
+    #[cfg(feature = "registry")]
+    #[inline]
+    unsafe fn register(&self, ptr: *mut u8, layout: Layout) {
+        if !ptr.is_null() {
+            let charged = self.alloc.usable_size(ptr, layout);
+            crate::registry::insert(ptr, layout, charged, self.pool);
+        }
+    }
+
+    /// Same to `dealloc` except for this method does not update the statistics.
+    #[inline]
+    unsafe fn dealloc_once(&self, ptr: *mut u8, layout: Layout) {
+        debug_assert!(!ptr.is_null());
+
+        let size = self.alloc.usable_size(ptr, layout);
+        self.pool.decrease_size(size);
+
+        self.alloc.dealloc(ptr, layout);
+    }
+
+    /// Same to `realloc` except for this method does not call the shrinkers.
+    #[inline]
+    unsafe fn realloc_once(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+
+        if layout.size() < new_size && self.pool.is_limited() {
+            // The usable size is not known until the memory is allocated.
+            // Allocate a new memory and check the limit before releasing `ptr` .
+            let ptr_ = self.alloc_once(new_layout);
+
+            if !ptr_.is_null() {
+                ptr::copy_nonoverlapping(ptr, ptr_, layout.size());
+                self.dealloc_once(ptr, layout);
+            }
+
+            return ptr_;
+        }
+
+        // The usable size can change even if the memory is reallocated in place.
+        let old_size = self.alloc.usable_size(ptr, layout);
+        let ptr_ = self.alloc.realloc(ptr, layout, new_size);
+
+        if !ptr_.is_null() {
+            let new_size = self.alloc.usable_size(ptr_, new_layout);
+
+            if old_size < new_size {
+                self.pool.increase_size(new_size - old_size);
+            } else {
+                self.pool.decrease_size(old_size - new_size);
+            }
+        }
+
+        ptr_
+    }
+}
+
+unsafe impl<A> GlobalAlloc for Counted<A>
+where
+    A: GlobalAlloc + UsableSize,
+{
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .pool
+            .retry_with_shrink(layout.size(), || self.alloc_once(layout));
+        self.pool.record_alloc(ptr, layout);
+        #[cfg(feature = "registry")]
+        self.register(ptr, layout);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .pool
+            .retry_with_shrink(layout.size(), || self.alloc_zeroed_once(layout));
+        self.pool.record_alloc(ptr, layout);
+        #[cfg(feature = "registry")]
+        self.register(ptr, layout);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        #[cfg(feature = "check")]
+        crate::registry::check("realloc", ptr, layout, self.pool);
+
+        // Unregisters `ptr` before the backend releases it, and restores it on failure.
+        #[cfg(feature = "registry")]
+        let removed = crate::registry::remove(ptr);
+
+        let growing = new_size.saturating_sub(layout.size());
+        let ptr_ = self
+            .pool
+            .retry_with_shrink(growing, || self.realloc_once(ptr, layout, new_size));
+        self.pool.record_realloc(ptr_, layout, new_size);
+
+        #[cfg(feature = "registry")]
+        if ptr_.is_null() {
+            if let Some(removed) = removed {
+                crate::registry::restore(ptr, removed);
+            }
+        } else {
+            let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+            self.register(ptr_, new_layout);
+        }
+        ptr_
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "check")]
+        crate::registry::check("dealloc", ptr, layout, self.pool);
+
+        self.pool.record_dealloc(layout);
+        #[cfg(feature = "registry")]
+        drop(crate::registry::remove(ptr));
+        self.dealloc_once(ptr, layout);
+    }
+}
+
+impl<A> UsableSize for Counted<A>
+where
+    A: UsableSize,
+{
+    #[inline]
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        self.alloc.usable_size(ptr, layout)
+    }
+}
+
+/// Backend of [`CAlloc`] and [`CPoolAlloc`] , which allocates memory through `std::alloc::alloc`
+/// and so on.
+///
+/// Under feature `dont_dump` , it allocates memory from the regions excluded from core dumps
+/// instead, mapping each allocation larger than 1024 bytes by itself. Under feature `poison` ,
+/// it poisons and quarantines the freed memory. (See `poison.rs` .)
+///
+/// [`CAlloc`]: struct.CAlloc.html
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+#[derive(Clone, Copy)]
+pub struct Heap;
+
+// Under feature `layout_size` , `Heap` allocates the size rounded up to `SIZE_GRANULE` so that
+// the charged size is actually available.
+unsafe impl GlobalAlloc for Heap {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        BACKEND.alloc(granule_layout(layout))
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        BACKEND.alloc_zeroed(granule_layout(layout))
+    }
+
+    #[cfg(not(feature = "poison"))]
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        BACKEND.realloc(ptr, granule_layout(layout), granule_round(new_size))
+    }
+
+    #[cfg(feature = "poison")]
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let layout = granule_layout(layout);
+        crate::poison::realloc(&BACKEND, ptr, layout, granule_round(new_size))
+    }
+
+    #[cfg(not(feature = "poison"))]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        BACKEND.dealloc(ptr, granule_layout(layout));
+    }
+
+    #[cfg(feature = "poison")]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        crate::poison::dealloc(&BACKEND, ptr, granule_layout(layout));
+    }
+}
+
+/// Allocator through `std::alloc::alloc` and so on, which `Heap` uses unless feature `dont_dump`
+/// is enabled.
+#[cfg(not(feature = "dont_dump"))]
+struct Std;
+
+#[cfg(not(feature = "dont_dump"))]
+unsafe impl GlobalAlloc for Std {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        std::alloc::alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        std::alloc::alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        std::alloc::realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        std::alloc::dealloc(ptr, layout);
+    }
+}
+
+/// The allocator which `Heap` delegates to.
+#[cfg(not(feature = "dont_dump"))]
+static BACKEND: Std = Std;
+
+/// Pool holding the slabs of [`REGIONS`] .
+///
+/// The memory is charged to the pools of `CAlloc` and `CPoolAlloc` by `Counted` as well; this
+/// pool is just for the slabs.
+///
+/// [`REGIONS`]: static.REGIONS.html
+#[cfg(feature = "dont_dump")]
+static REGION_POOL: CachePool = CachePool::new();
+
+/// The allocator of the memory excluded from core dumps.
+///
+/// It is not a suballocator of large regions; the slabs hold the allocations of 1024 bytes
+/// or less (a quarter of the page at most,) and each larger allocation is mapped by itself.
+#[cfg(feature = "dont_dump")]
+static REGIONS: CMmapAlloc = CMmapAlloc::with_pool(&REGION_POOL)
+    .slab(true)
+    .dont_dump(true);
+
+/// Allocator through [`REGIONS`] , which `Heap` uses under feature `dont_dump` .
+///
+/// It does not call the shrinkers; `Counted` calls them on failure. The layouts aligned to
+/// more than the page size are allocated through `std::alloc::alloc` and so on, because
+/// `mmap` cannot align them.
+///
+/// [`REGIONS`]: static.REGIONS.html
+#[cfg(feature = "dont_dump")]
+struct Region;
+
+#[cfg(feature = "dont_dump")]
+impl Region {
+    /// Returns whether `layout` is allocated from the heap instead of [`REGIONS`] or not.
+    ///
+    /// [`REGIONS`]: static.REGIONS.html
+    #[inline]
+    fn is_over_aligned(layout: Layout) -> bool {
+        mmap_allocator::page_size() < layout.align()
+    }
+}
+
+#[cfg(feature = "dont_dump")]
+unsafe impl GlobalAlloc for Region {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if Self::is_over_aligned(layout) {
+            std::alloc::alloc(layout)
+        } else {
+            REGIONS.alloc_once(layout)
+        }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if Self::is_over_aligned(layout) {
+            std::alloc::alloc_zeroed(layout)
+        } else {
+            REGIONS.alloc_zeroed_once(layout)
+        }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if Self::is_over_aligned(layout) {
+            std::alloc::realloc(ptr, layout, new_size)
+        } else {
+            REGIONS.realloc_once(ptr, layout, new_size)
+        }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if Self::is_over_aligned(layout) {
+            std::alloc::dealloc(ptr, layout);
+        } else {
+            REGIONS.dealloc_once(ptr, layout);
+        }
+    }
+}
+
+/// The allocator which `Heap` delegates to.
+#[cfg(feature = "dont_dump")]
+static BACKEND: Region = Region;
+
+impl UsableSize for Heap {
+    #[inline]
+    unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        heap_size(ptr, layout)
+    }
+}
+
+/// Returns the byte size available from `ptr` , which the heap allocated with `layout` .
+#[cfg(not(any(feature = "layout_size", feature = "dont_dump")))]
+#[inline]
+unsafe fn heap_size(ptr: *mut u8, _layout: Layout) -> usize {
+    crate::allocating_size(ptr)
+}
+
+/// Returns the byte size available from `ptr` , which the heap allocated with `layout` .
+#[cfg(all(not(feature = "layout_size"), feature = "dont_dump"))]
+#[inline]
+unsafe fn heap_size(ptr: *mut u8, layout: Layout) -> usize {
+    if Region::is_over_aligned(layout) {
+        crate::allocating_size(ptr)
+    } else {
+        REGIONS.usable_size(ptr, layout)
+    }
+}
+
+/// Returns the byte size available from `ptr` , which the heap allocated with `layout` .
+#[cfg(feature = "layout_size")]
+#[inline]
+unsafe fn heap_size(_ptr: *mut u8, layout: Layout) -> usize {
+    granule_round(layout.size())
+}
+
+/// Returns `size` rounded up to the multiple of [`SIZE_GRANULE`] under feature `layout_size` ,
+/// or `size` itself otherwise.
+///
+/// [`SIZE_GRANULE`]: constant.SIZE_GRANULE.html
+#[inline]
+fn granule_round(size: usize) -> usize {
+    #[cfg(feature = "layout_size")]
+    {
+        size.div_ceil(SIZE_GRANULE) * SIZE_GRANULE
+    }
+    #[cfg(not(feature = "layout_size"))]
+    {
+        size
+    }
+}
+
+/// Returns `layout` whose size is rounded by [`granule_round`] .
+///
+/// [`granule_round`]: fn.granule_round.html
+#[inline]
+fn granule_layout(layout: Layout) -> Layout {
+    unsafe { Layout::from_size_align_unchecked(granule_round(layout.size()), layout.align()) }
+}
+
+/// Granule of the size which [`CAlloc`] and [`CPoolAlloc`] allocate and charge under feature
+/// `layout_size` .
+///
+/// It is 1 unless environment variable `MOUSE_CACHE_ALLOC_GRANULE` is set at build time.
+///
+/// [`CAlloc`]: struct.CAlloc.html
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+#[cfg(feature = "layout_size")]
+pub const SIZE_GRANULE: usize = parse_granule(option_env!("MOUSE_CACHE_ALLOC_GRANULE"));
+
+#[cfg(feature = "layout_size")]
+const fn parse_granule(s: Option<&str>) -> usize {
+    let bytes = match s {
+        None => return 1,
+        Some(s) => s.as_bytes(),
+    };
+
+    let mut ret: usize = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "MOUSE_CACHE_ALLOC_GRANULE must be a decimal number."
+        );
+        ret = ret * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+
+    assert!(
+        ret.is_power_of_two(),
+        "MOUSE_CACHE_ALLOC_GRANULE must be a power of 2."
+    );
+    ret
+}
+
+/// Implementation for `GlobalAlloc` to charge the heap memory to a pool.
+pub type SizeAllocator = Counted<Heap>;
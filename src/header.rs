@@ -0,0 +1,293 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of `CHeaderAlloc` , which prefixes each allocation with a header.
+
+use crate::{CachePool, DEFAULT_POOL};
+use core::alloc::{GlobalAlloc, Layout};
+use core::{cmp, mem, ptr};
+
+/// Prefix of each allocation of [`CHeaderAlloc`] .
+///
+/// [`CHeaderAlloc`]: struct.CHeaderAlloc.html
+#[repr(C)]
+struct Header {
+    /// `Layout::size()` which the user requested.
+    size: usize,
+    /// The pool which the allocation is charged to.
+    pool: &'static CachePool,
+}
+
+/// Implementation for `GlobalAlloc` to allocate/deallocate memory for cache.
+/// Unlike to [`CPoolAlloc`] , `CHeaderAlloc` stores a small header in front of each
+/// allocation, which records the requested size and the pool.
+///
+/// `CHeaderAlloc` charges the requested size and the header size, so it never calls
+/// `malloc_usable_size` . (The charged bytes, i.e. `CacheStats::charged_bytes` , are larger
+/// than `CacheStats::requested_bytes` by the headers.)
+///
+/// Because the header knows the pool, `dealloc` and `realloc` always charge to the pool which
+/// allocated the memory, even if `self` is made for another pool. [`pool_of`] returns the pool
+/// of each allocation.
+///
+/// ```
+/// use mouse_cache_alloc::{CHeaderAlloc, CachePool};
+/// use std::alloc::{GlobalAlloc, Layout};
+///
+/// static POOL0: CachePool = CachePool::new();
+/// static POOL1: CachePool = CachePool::new();
+///
+/// let alloc0 = CHeaderAlloc::with_pool(&POOL0);
+/// let alloc1 = CHeaderAlloc::with_pool(&POOL1);
+///
+/// let layout = Layout::new::<[u8; 100]>();
+/// unsafe {
+///     let ptr = alloc0.alloc(layout);
+///     assert!(core::ptr::eq(&POOL0, CHeaderAlloc::pool_of(ptr)));
+///     assert_eq!(100, CHeaderAlloc::requested_size(ptr));
+///     assert!(100 < POOL0.cache_size());
+///
+///     // `alloc1` releases the memory from `POOL0` .
+///     alloc1.dealloc(ptr, layout);
+///     assert_eq!(0, POOL0.cache_size());
+///     assert_eq!(0, POOL1.cache_size());
+/// }
+/// ```
+///
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+/// [`pool_of`]: #method.pool_of
+#[derive(Clone, Copy)]
+pub struct CHeaderAlloc {
+    pool: &'static CachePool,
+}
+
+impl Default for CHeaderAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CHeaderAlloc {
+    /// Creates a new instance charging to the default pool.
+    #[inline]
+    pub const fn new() -> Self {
+        Self::with_pool(&DEFAULT_POOL)
+    }
+
+    /// Creates a new instance charging to `pool` .
+    #[inline]
+    pub const fn with_pool(pool: &'static CachePool) -> Self {
+        Self { pool }
+    }
+
+    /// Returns the pool which `self` charges to.
+    #[inline]
+    pub fn pool(&self) -> &'static CachePool {
+        self.pool
+    }
+
+    /// Returns the pool which `ptr` is charged to.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `CHeaderAlloc` allocated, and must not have been deallocated yet.
+    #[inline]
+    pub unsafe fn pool_of(ptr: *const u8) -> &'static CachePool {
+        (*header(ptr)).pool
+    }
+
+    /// Returns the byte size which the user requested to allocate `ptr` .
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `CHeaderAlloc` allocated, and must not have been deallocated yet.
+    #[inline]
+    pub unsafe fn requested_size(ptr: *const u8) -> usize {
+        (*header(ptr)).size
+    }
+
+    /// Returns the byte size which `ptr` is charged. (The requested size and the header
+    /// size.)
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `CHeaderAlloc` allocated with `layout` , and must not have been
+    /// deallocated yet.
+    #[inline]
+    pub unsafe fn charged_size(ptr: *const u8, layout: Layout) -> usize {
+        offset(layout.align()) + Self::requested_size(ptr)
+    }
+}
+
+impl CHeaderAlloc {
+    /// Same to `alloc` or `alloc_zeroed` except for this method does not call the shrinkers.
+    #[inline]
+    unsafe fn alloc_once(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let outer = match outer_layout(layout.size(), layout.align()) {
+            None => return ptr::null_mut(),
+            Some(l) => l,
+        };
+
+        if !self.pool.try_increase_size(outer.size()) {
+            return ptr::null_mut();
+        }
+
+        let base = if zeroed {
+            std::alloc::alloc_zeroed(outer)
+        } else {
+            std::alloc::alloc(outer)
+        };
+
+        if base.is_null() {
+            self.pool.decrease_size(outer.size());
+            return ptr::null_mut();
+        }
+
+        init(base, layout.align(), layout.size(), self.pool)
+    }
+
+    /// Same to `realloc` except for this method does not call the shrinkers.
+    #[inline]
+    unsafe fn realloc_once(
+        pool: &'static CachePool,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let old = outer_layout((*header(ptr)).size, layout.align()).unwrap();
+        let new = match outer_layout(new_size, layout.align()) {
+            None => return ptr::null_mut(),
+            Some(l) => l,
+        };
+        let base = ptr.sub(offset(layout.align()));
+
+        if old.size() < new.size() {
+            let growing = new.size() - old.size();
+            if !pool.try_increase_size(growing) {
+                return ptr::null_mut();
+            }
+
+            let base = std::alloc::realloc(base, old, new.size());
+            if base.is_null() {
+                pool.decrease_size(growing);
+                return ptr::null_mut();
+            }
+
+            init(base, layout.align(), new_size, pool)
+        } else {
+            let base = std::alloc::realloc(base, old, new.size());
+            if base.is_null() {
+                return ptr::null_mut();
+            }
+
+            pool.decrease_size(old.size() - new.size());
+            init(base, layout.align(), new_size, pool)
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CHeaderAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocating = offset(layout.align()).saturating_add(layout.size());
+        let ptr = self
+            .pool
+            .retry_with_shrink(allocating, || self.alloc_once(layout, false));
+        self.pool.record_alloc(ptr, layout);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let allocating = offset(layout.align()).saturating_add(layout.size());
+        let ptr = self
+            .pool
+            .retry_with_shrink(allocating, || self.alloc_once(layout, true));
+        self.pool.record_alloc(ptr, layout);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        debug_assert!(!ptr.is_null());
+        debug_assert_eq!(layout.size(), Self::requested_size(ptr));
+
+        let pool = Self::pool_of(ptr);
+        let growing = new_size.saturating_sub(layout.size());
+        let ptr =
+            pool.retry_with_shrink(growing, || Self::realloc_once(pool, ptr, layout, new_size));
+        pool.record_realloc(ptr, layout, new_size);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        debug_assert!(!ptr.is_null());
+        debug_assert_eq!(layout.size(), Self::requested_size(ptr));
+
+        let pool = Self::pool_of(ptr);
+        let outer = outer_layout(Self::requested_size(ptr), layout.align()).unwrap();
+        let base = ptr.sub(offset(layout.align()));
+
+        pool.record_dealloc(layout);
+        std::alloc::dealloc(base, outer);
+        pool.decrease_size(outer.size());
+    }
+}
+
+/// Returns the byte size from the head of the allocation to the pointer returned to the user.
+#[inline]
+fn offset(align: usize) -> usize {
+    cmp::max(mem::size_of::<Header>(), align)
+}
+
+/// Returns the layout including the header, or `None` if overflowed.
+#[inline]
+fn outer_layout(size: usize, align: usize) -> Option<Layout> {
+    let align = cmp::max(align, mem::align_of::<Header>());
+    let size = offset(align).checked_add(size)?;
+    Layout::from_size_align(size, align).ok()
+}
+
+/// Returns the pointer to the header of `ptr` .
+#[inline]
+unsafe fn header(ptr: *const u8) -> *const Header {
+    ptr.sub(mem::size_of::<Header>()) as *const Header
+}
+
+/// Writes the header in `base` and returns the pointer for the user.
+#[inline]
+unsafe fn init(base: *mut u8, align: usize, size: usize, pool: &'static CachePool) -> *mut u8 {
+    let ptr = base.add(offset(align));
+    let header = ptr.sub(mem::size_of::<Header>()) as *mut Header;
+    header.write(Header { size, pool });
+    ptr
+}
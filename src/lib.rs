@@ -30,53 +30,376 @@
 // limitations under the License.
 
 #![deny(missing_docs)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 //! # mouse-cache-alloc
+//!
+//! ## Features
+//!
+//! - `allocator_api` : Implements `core::alloc::Allocator` for [`CAlloc`] , [`CPoolAlloc`] , and
+//!   [`CMmapAlloc`] , so that each collection can be charged to the cache with `Vec::new_in`
+//!   and so on. This feature requires nightly rust.
+//!
+//!   On stable rust, use [`CacheBox`] , [`CacheVec`] , and [`CacheString`] instead.
+//! - `check` : [`CAlloc`] and [`CPoolAlloc`] validate each pointer passed to `dealloc` and
+//!   `realloc` , and abort the process with a diagnostic if the pointer is not allocated by
+//!   them, is freed twice, or is passed with a layout which does not fit. (The layout fits if
+//!   the alignment is the same and the size is between the requested size and the usable
+//!   size.) This implies feature `registry` .
+//!
+//!   The check relies on the registry. If `Counted` is the global allocator, the allocations of
+//!   the registry itself are not registered, and the foreign pointers are not detected while
+//!   any of them is alive.
+//! - `guard_pages` : Enables [`CMmapAlloc::guard_pages`] to detect buffer overruns. This is for
+//!   debugging.
+//! - `histogram` : Maintains the histogram of the live allocation sizes in each pool.
+//!   (See `SizeHistogram` .)
+//! - `dont_dump` : [`CAlloc`] and [`CPoolAlloc`] allocate memory from the regions excluded from
+//!   core dumps (`MADV_DONTDUMP` on Linux) instead of `std::alloc::alloc` . (See
+//!   [`CMmapAlloc::dont_dump`] .)
+//!
+//!   The allocations of 1024 bytes or less share the slab pages, but each larger allocation is
+//!   mapped by itself; it costs `mmap` , `madvise` and `munmap` , and the whole pages are
+//!   charged. The layouts aligned to more than the page size are allocated through
+//!   `std::alloc::alloc` and are not excluded from core dumps.
+//! - `layout_size` : [`CAlloc`] and [`CPoolAlloc`] allocate and charge `Layout::size()`
+//!   rounded up to the multiple of [`SIZE_GRANULE`] instead of charging [`allocating_size`] ,
+//!   so that they work without `malloc_usable_size` .
+//!
+//!   `SIZE_GRANULE` is 1 by default. Set environment variable `MOUSE_CACHE_ALLOC_GRANULE` at
+//!   build time to change it. It must be a power of 2.
+//! - `poison` : [`CAlloc`] and [`CPoolAlloc`] fill freed memory with `POISON` and keep it in
+//!   a bounded quarantine. When the memory leaves the quarantine, the poison is checked to
+//!   detect use after free. (See `set_corruption_handler` .) This is for debugging and
+//!   hardening.
+//! - `registry` : Records the live allocations of [`CAlloc`] and [`CPoolAlloc`] to list the
+//!   leaked memory and to verify the cache size. (See `CachePool::live_allocations` .) This is
+//!   for debugging.
+//!
+//! [`CAlloc`]: struct.CAlloc.html
+//! [`CPoolAlloc`]: struct.CPoolAlloc.html
+//! [`CMmapAlloc`]: struct.CMmapAlloc.html
+//! [`CacheBox`]: struct.CacheBox.html
+//! [`CacheVec`]: struct.CacheVec.html
+//! [`CacheString`]: struct.CacheString.html
+//! [`CMmapAlloc::dont_dump`]: struct.CMmapAlloc.html#method.dont_dump
+//! [`CMmapAlloc::guard_pages`]: struct.CMmapAlloc.html#method.guard_pages
+//! [`SIZE_GRANULE`]: constant.SIZE_GRANULE.html
+//! [`allocating_size`]: fn.allocating_size.html
 
 use core::alloc::{GlobalAlloc, Layout};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, ptr};
 use std::os::raw::c_void;
 
+#[cfg(feature = "allocator_api")]
+mod allocator;
+mod cache_box;
+mod cache_string;
+mod cache_vec;
+mod counted;
+mod header;
+mod lru;
+mod os;
+#[cfg(feature = "poison")]
+mod poison;
+mod pool;
+mod purge;
+#[cfg(feature = "registry")]
+mod registry;
+mod shard;
+mod shrinker;
+mod slab;
+mod stats;
+
+pub use cache_box::CacheBox;
+pub use cache_string::CacheString;
+pub use cache_vec::{CacheVec, Drain, IntoIter, TryReserveError};
+#[cfg(feature = "layout_size")]
+pub use counted::SIZE_GRANULE;
+pub use counted::{Counted, UsableSize};
+use counted::{Heap, SizeAllocator};
+pub use header::CHeaderAlloc;
+pub use lru::{LruCache, LruIter};
+pub use os::LockError;
+#[cfg(feature = "poison")]
+pub use poison::{flush_quarantine, set_corruption_handler, Corruption, POISON, QUARANTINE_LEN};
+pub use pool::CachePool;
+pub use purge::Purged;
+#[cfg(feature = "registry")]
+pub use registry::{set_registry_backtrace, LiveAllocation};
+pub use shard::SHARDS;
+pub use stats::CacheStats;
+#[cfg(feature = "histogram")]
+pub use stats::{SizeHistogram, HISTOGRAM_BUCKETS};
+
+pub use shrinker::{
+    register_shrinker, shrink, unregister_shrinker, Shrinker, ShrinkerId, MAX_SHRINKERS,
+};
+
 /// Same to `std::alloc::alloc` except for this method is for cache memory.
+///
+/// If the allocation would make [`cache_size`] exceed [`cache_limit`] or the backend fails to
+/// allocate memory, calls the shrinkers and retries.
+/// Returns null if the retry fails again.
+///
+/// # Safety
+///
+/// See `std::alloc::GlobalAlloc::alloc` .
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`cache_limit`]: fn.cache_limit.html
 #[inline]
 pub unsafe fn alloc(layout: Layout) -> *mut u8 {
     SIZE_ALLOC.alloc(layout)
 }
 
 /// Same to `std::alloc::alloc_zeroed` except for this method is for cache memory.
+///
+/// If the allocation would make [`cache_size`] exceed [`cache_limit`] or the backend fails to
+/// allocate memory, calls the shrinkers and retries.
+/// Returns null if the retry fails again.
+///
+/// # Safety
+///
+/// See `std::alloc::GlobalAlloc::alloc_zeroed` .
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`cache_limit`]: fn.cache_limit.html
 #[inline]
 pub unsafe fn alloc_zeroed(layout: Layout) -> *mut u8 {
     SIZE_ALLOC.alloc_zeroed(layout)
 }
 
 /// Same to `std::alloc::realloc` except for this method is for cache memory.
+///
+/// If growing the memory would make [`cache_size`] exceed [`cache_limit`] or the backend fails
+/// to allocate memory, calls the shrinkers and retries.
+/// Returns null if the retry fails again. (`ptr` is still valid in that case.)
+///
+/// # Safety
+///
+/// See `std::alloc::GlobalAlloc::realloc` .
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`cache_limit`]: fn.cache_limit.html
 #[inline]
 pub unsafe fn realloc(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
     SIZE_ALLOC.realloc(ptr, layout, new_size)
 }
 
 /// Same to `std::alloc::dealloc` except for this method is for cache memory.
+///
+/// # Safety
+///
+/// See `std::alloc::GlobalAlloc::dealloc` .
 #[inline]
 pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
     SIZE_ALLOC.dealloc(ptr, layout);
 }
 
-/// Returns how many bytes memory is allocated for cache.
+/// Returns how many bytes memory is allocated for cache in the default pool.
+///
+/// See [`CachePool::cache_size`] for details.
+///
+/// [`CachePool::cache_size`]: struct.CachePool.html#method.cache_size
 #[inline]
 pub fn cache_size() -> usize {
-    SIZE_ALLOC.allocating_size()
+    DEFAULT_POOL.cache_size()
+}
+
+/// Returns how many bytes memory is allocated for cache in the default pool excluding the
+/// credits of the shards.
+///
+/// See [`CachePool::set_shard_batch`] for details.
+///
+/// [`CachePool::set_shard_batch`]: struct.CachePool.html#method.set_shard_batch
+#[inline]
+pub fn cache_size_exact() -> usize {
+    DEFAULT_POOL.cache_size_exact()
+}
+
+/// Returns how many bytes of [`cache_size`] are locked in RAM in the default pool.
+///
+/// See [`CMmapAlloc::mlock`] for details.
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`CMmapAlloc::mlock`]: struct.CMmapAlloc.html#method.mlock
+#[inline]
+pub fn cache_locked_size() -> usize {
+    DEFAULT_POOL.locked_size()
+}
+
+/// Returns how many bytes of [`cache_size`] are purgeable in the default pool.
+///
+/// See [`CMmapAlloc::purge`] for details.
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`CMmapAlloc::purge`]: struct.CMmapAlloc.html#method.purge
+#[inline]
+pub fn cache_purgeable_size() -> usize {
+    DEFAULT_POOL.purgeable_size()
+}
+
+/// Returns the snapshot of the allocation statistics of the default pool.
+///
+/// See [`CacheStats`] for details.
+///
+/// [`CacheStats`]: struct.CacheStats.html
+#[inline]
+pub fn cache_stats() -> CacheStats {
+    DEFAULT_POOL.stats()
+}
+
+/// Returns the live allocations in the default pool.
+///
+/// This function is available only if feature `registry` is enabled.
+/// See [`CachePool::live_allocations`] for details.
+///
+/// [`CachePool::live_allocations`]: struct.CachePool.html#method.live_allocations
+#[cfg(feature = "registry")]
+#[inline]
+pub fn cache_live_allocations() -> Vec<LiveAllocation> {
+    DEFAULT_POOL.live_allocations()
+}
+
+/// Returns the sum of the bytes charged for [`cache_live_allocations`] .
+///
+/// This function is available only if feature `registry` is enabled.
+/// See [`CachePool::registered_size`] for details.
+///
+/// [`cache_live_allocations`]: fn.cache_live_allocations.html
+/// [`CachePool::registered_size`]: struct.CachePool.html#method.registered_size
+#[cfg(feature = "registry")]
+#[inline]
+pub fn cache_registered_size() -> usize {
+    DEFAULT_POOL.registered_size()
+}
+
+/// Returns the snapshot of the histogram of the live allocation sizes in the default pool.
+///
+/// This function is available only if feature `histogram` is enabled.
+/// See [`SizeHistogram`] for details.
+///
+/// [`SizeHistogram`]: struct.SizeHistogram.html
+#[cfg(feature = "histogram")]
+#[inline]
+pub fn cache_histogram() -> SizeHistogram {
+    DEFAULT_POOL.histogram()
+}
+
+/// Sets the upper limit of the cache memory size to `bytes` .
+///
+/// After this method is called, [`alloc`] , [`alloc_zeroed`] , and growing [`realloc`] return
+/// null if the allocation would make [`cache_size`] exceed `bytes` .
+/// The limit is checked atomically, so concurrent allocations never make the cache size
+/// overshoot it.
+///
+/// Calling this method does not release any memory even if [`cache_size`] already exceeds
+/// `bytes` ; it only makes the following allocations fail.
+///
+/// Note that growing [`realloc`] is never done in place while any limit is set.
+/// The usable size of the reallocated memory is not known until it is allocated, so it
+/// allocates new memory, checks the limit, copies the contents, and then releases the old one.
+/// (The old and the new memory are charged at the same time for a moment.)
+/// Shrinking `realloc` is done in place as usual.
+///
+/// The default limit is `usize::MAX` , i.e. unlimited.
+///
+/// [`alloc`]: fn.alloc.html
+/// [`alloc_zeroed`]: fn.alloc_zeroed.html
+/// [`realloc`]: fn.realloc.html
+/// [`cache_size`]: fn.cache_size.html
+#[inline]
+pub fn set_cache_limit(bytes: usize) {
+    DEFAULT_POOL.set_limit(bytes);
+}
+
+/// Returns the upper limit of the cache memory size.
+///
+/// See [`set_cache_limit`] for details.
+///
+/// [`set_cache_limit`]: fn.set_cache_limit.html
+#[inline]
+pub fn cache_limit() -> usize {
+    DEFAULT_POOL.limit()
+}
+
+/// Sets the high and the low watermarks of the cache memory size.
+///
+/// The callback registered as `on_high` by [`set_pressure_callbacks`] is called when
+/// [`cache_size`] reaches `high` , and then `on_low` is called when [`cache_size`] falls
+/// to `low` or less.
+/// Neither callback is called again until the other one is called. (i.e. hysteresis.)
+///
+/// The default high watermark is `usize::MAX` and the default low watermark is 0.
+///
+/// # Panics
+///
+/// Panics if `high` is less than `low` .
+///
+/// [`set_pressure_callbacks`]: fn.set_pressure_callbacks.html
+/// [`cache_size`]: fn.cache_size.html
+#[inline]
+pub fn set_cache_watermarks(high: usize, low: usize) {
+    DEFAULT_POOL.set_watermarks(high, low);
+}
+
+/// Returns the high and the low watermarks of the cache memory size.
+///
+/// See [`set_cache_watermarks`] for details.
+///
+/// [`set_cache_watermarks`]: fn.set_cache_watermarks.html
+#[inline]
+pub fn cache_watermarks() -> (usize, usize) {
+    DEFAULT_POOL.watermarks()
+}
+
+/// Registers the callbacks called when [`cache_size`] crosses the watermarks.
+///
+/// `on_high` is called when [`cache_size`] reaches the high watermark, and `on_low` is called
+/// when it falls back to the low watermark. Both take the new cache size as the argument.
+/// `None` unregisters the callback.
+///
+/// The callbacks are called in the thread allocating or deallocating the cache memory, in the
+/// middle of the allocation.
+/// They should return quickly (e.g. waking up an eviction thread) and must not allocate memory
+/// through this crate recursively.
+///
+/// See [`set_cache_watermarks`] for details.
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`set_cache_watermarks`]: fn.set_cache_watermarks.html
+#[inline]
+pub fn set_pressure_callbacks(on_high: Option<fn(usize)>, on_low: Option<fn(usize)>) {
+    DEFAULT_POOL.set_pressure_callbacks(on_high, on_low);
+}
+
+/// Returns `true` if [`cache_size`] has reached the high watermark and has not fallen to the
+/// low watermark yet.
+///
+/// See [`set_cache_watermarks`] for details.
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`set_cache_watermarks`]: fn.set_cache_watermarks.html
+#[inline]
+pub fn is_under_pressure() -> bool {
+    DEFAULT_POOL.is_under_pressure()
 }
 
 /// Increases caching memory size by `bytes` and returns the new size.
+///
+/// This method ignores [`cache_limit`] .
+///
+/// [`cache_limit`]: fn.cache_limit.html
 #[inline]
 pub fn increase_cache_size(bytes: usize) -> usize {
-    SIZE_ALLOC.increase_size(bytes)
+    DEFAULT_POOL.increase_size(bytes)
 }
 
 /// Decreases caching memory size by `bytes` and returns the new size.
 #[inline]
 pub fn decrease_cache_size(bytes: usize) -> usize {
-    SIZE_ALLOC.decrease_size(bytes)
+    DEFAULT_POOL.decrease_size(bytes)
 }
 
 /// Implementation for `GlobalAlloc` to allocate/deallocate memory for cache.
@@ -118,95 +441,64 @@ unsafe impl GlobalAlloc for CAlloc {
     }
 }
 
-static SIZE_ALLOC: SizeAllocator = SizeAllocator::new();
-
-/// Implementation for `GlobalAlloc` to store allocating memory size.
-struct SizeAllocator {
-    size: AtomicUsize,
-}
-
-impl SizeAllocator {
-    /// Creates a new instance with no allocating memory.
-    #[inline]
-    pub const fn new() -> Self {
-        Self {
-            size: AtomicUsize::new(0),
-        }
-    }
-
-    /// Returns the total byte size of allocating memory.
-    #[inline]
-    pub fn allocating_size(&self) -> usize {
-        self.size.load(Ordering::Relaxed)
-    }
+/// Implementation for `GlobalAlloc` to allocate/deallocate memory for cache.
+/// Unlike to [`CAlloc`] , `CPoolAlloc` charges the memory to the specified pool.
+///
+/// See [`CachePool`] for the example.
+///
+/// [`CAlloc`]: struct.CAlloc.html
+/// [`CachePool`]: struct.CachePool.html
+#[derive(Clone, Copy)]
+pub struct CPoolAlloc(SizeAllocator);
 
-    /// Increase the allocating memory size by `bytes` and returns the new byte size.
+impl CPoolAlloc {
+    /// Creates a new instance charging to `pool` .
     #[inline]
-    pub fn increase_size(&self, bytes: usize) -> usize {
-        self.size.fetch_add(bytes, Ordering::Acquire) + bytes
+    pub const fn new(pool: &'static CachePool) -> Self {
+        Self(SizeAllocator::new(Heap, pool))
     }
 
-    /// Decrease the allocating memory size by `bytes` and returns the new byte size.
+    /// Returns the pool which `self` charges to.
     #[inline]
-    pub fn decrease_size(&self, bytes: usize) -> usize {
-        self.size.fetch_sub(bytes, Ordering::Acquire) - bytes
+    pub fn pool(&self) -> &'static CachePool {
+        self.0.pool()
     }
 }
 
-unsafe impl GlobalAlloc for SizeAllocator {
+unsafe impl GlobalAlloc for CPoolAlloc {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let ptr = std::alloc::alloc(layout);
-
-        if !ptr.is_null() {
-            let size = allocating_size(ptr);
-            self.size.fetch_add(size, Ordering::Acquire);
-        }
-
-        ptr
+        self.0.alloc(layout)
     }
 
     #[inline]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        let ptr = std::alloc::alloc_zeroed(layout);
-
-        if !ptr.is_null() {
-            let size = allocating_size(ptr);
-            self.size.fetch_add(size, Ordering::Acquire);
-        }
-
-        ptr
+        self.0.alloc_zeroed(layout)
     }
 
     #[inline]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        let old_size = allocating_size(ptr);
-        let ptr_ = std::alloc::realloc(ptr, layout, new_size);
-
-        if (ptr_ != ptr) && !ptr_.is_null() {
-            let new_size = allocating_size(ptr_);
-
-            if old_size < new_size {
-                self.size.fetch_add(new_size - old_size, Ordering::SeqCst);
-            } else {
-                self.size.fetch_sub(old_size - new_size, Ordering::SeqCst);
-            }
-        }
-
-        ptr_
+        self.0.realloc(ptr, layout, new_size)
     }
 
     #[inline]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        debug_assert!(!ptr.is_null());
-
-        let size = allocating_size(ptr);
-        self.size.fetch_sub(size, Ordering::Release);
-
-        std::alloc::dealloc(ptr, layout);
+        self.0.dealloc(ptr, layout);
     }
 }
 
+/// Returns the default pool, which the crate functions like [`cache_size`] and [`CAlloc`] use.
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`CAlloc`]: struct.CAlloc.html
+#[inline]
+pub fn default_pool() -> &'static CachePool {
+    &DEFAULT_POOL
+}
+
+static DEFAULT_POOL: CachePool = CachePool::new();
+static SIZE_ALLOC: SizeAllocator = SizeAllocator::new(Heap, &DEFAULT_POOL);
+
 /// Returns size of memory allocated from heap.
 ///
 /// Argument `ptr` must fulfill the followings
@@ -228,7 +520,7 @@ unsafe impl GlobalAlloc for SizeAllocator {
 #[cfg(unix)]
 #[inline]
 pub unsafe fn allocating_size<T>(ptr: *const T) -> usize {
-    debug_assert_eq!(false, ptr.is_null());
+    debug_assert!(!ptr.is_null());
 
     malloc_usable_size(ptr as *const c_void)
 }
@@ -257,8 +549,125 @@ extern "C" {
 /// Implementation for `GlobalAlloc` to allocate/deallocate memory for cache.
 /// Unlike to [`CAlloc`] , the backend of `CMmapAlloc` is 'posix mmap'.
 ///
+/// `CMmapAlloc` charges the page-rounded size and respects [`cache_limit`] as well as
+/// [`CAlloc`] does.
+///
+/// # Slabs
+///
+/// By default, `CMmapAlloc` maps pages for each allocation, so even a 16 bytes allocation costs
+/// a whole page. If [`slab`] is enabled, small allocations (up to 1/4 of the page size and 1024
+/// bytes) are carved out of shared pages. ('slabs'.) Then, the pool is charged per slab page,
+/// and each slab page is returned to the OS as soon as it gets empty.
+///
+/// The slab pages are shared among the instances charging to the same pool only if they lock
+/// the memory (see [`mlock`] ) and exclude it from core dumps (see [`dont_dump`] ) alike.
+///
+/// ```
+/// use mouse_cache_alloc::{CMmapAlloc, CachePool};
+/// use std::alloc::{GlobalAlloc, Layout};
+///
+/// static POOL: CachePool = CachePool::new();
+/// let alloc = CMmapAlloc::with_pool(&POOL).slab(true);
+///
+/// let layout = Layout::new::<[u8; 16]>();
+/// unsafe {
+///     let ptrs: Vec<*mut u8> = (0..64).map(|_| alloc.alloc(layout)).collect();
+///     assert!(POOL.cache_size() < 64 * mmap_allocator::page_size());
+///
+///     for ptr in ptrs {
+///         alloc.dealloc(ptr, layout);
+///     }
+///     assert_eq!(0, POOL.cache_size());
+/// }
+/// ```
+///
+/// # Huge pages
+///
+/// [`huge_pages`] controls the transparent huge pages on Linux. If [`HugePages::Enabled`] is
+/// set, allocations of [`HUGE_PAGE_SIZE`] or larger are aligned to `HUGE_PAGE_SIZE` and
+/// advised with `MADV_HUGEPAGE` . Such an allocation is charged with the size rounded up to the
+/// multiple of `HUGE_PAGE_SIZE` .
+///
+/// ```
+/// use mouse_cache_alloc::{CMmapAlloc, CachePool, HugePages, HUGE_PAGE_SIZE};
+/// use std::alloc::{GlobalAlloc, Layout};
+///
+/// static POOL: CachePool = CachePool::new();
+/// let alloc = CMmapAlloc::with_pool(&POOL).huge_pages(HugePages::Enabled);
+///
+/// let layout = Layout::from_size_align(HUGE_PAGE_SIZE + 1, 8).unwrap();
+/// unsafe {
+///     let ptr = alloc.alloc(layout);
+///     assert_eq!(0, ptr as usize % HUGE_PAGE_SIZE);
+///     assert_eq!(2 * HUGE_PAGE_SIZE, POOL.cache_size());
+///
+///     alloc.dealloc(ptr, layout);
+/// }
+/// ```
+///
+/// Memory must be deallocated and reallocated by `CMmapAlloc` with the same options as the one
+/// which allocated it.
+///
 /// [`CAlloc`]: struct.CAlloc.html
-pub struct CMmapAlloc(mmap_allocator::MmapAllocator);
+/// [`cache_limit`]: fn.cache_limit.html
+/// [`slab`]: #method.slab
+/// [`mlock`]: #method.mlock
+/// [`dont_dump`]: #method.dont_dump
+/// [`huge_pages`]: #method.huge_pages
+/// [`HugePages::Enabled`]: enum.HugePages.html#variant.Enabled
+/// [`HUGE_PAGE_SIZE`]: constant.HUGE_PAGE_SIZE.html
+#[derive(Clone, Copy)]
+pub struct CMmapAlloc {
+    alloc: mmap_allocator::MmapAllocator,
+    pool: &'static CachePool,
+    slab: bool,
+    huge_pages: HugePages,
+    dont_dump: bool,
+    mlock: bool,
+    #[cfg(feature = "guard_pages")]
+    guard_pages: GuardPages,
+}
+
+/// The size of the transparent huge pages, which [`CMmapAlloc`] aligns large allocations to.
+///
+/// [`CMmapAlloc`]: struct.CMmapAlloc.html
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Placement of the guard page of each allocation of [`CMmapAlloc`] .
+///
+/// See [`CMmapAlloc::guard_pages`] for details.
+///
+/// [`CMmapAlloc`]: struct.CMmapAlloc.html
+/// [`CMmapAlloc::guard_pages`]: struct.CMmapAlloc.html#method.guard_pages
+#[cfg(feature = "guard_pages")]
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum GuardPages {
+    /// Places no guard page. (Default.)
+    #[default]
+    Disabled,
+    /// Places the guard page after each allocation to detect overruns.
+    After,
+    /// Places the guard page before each allocation to detect underruns.
+    Before,
+}
+
+/// Policy of [`CMmapAlloc`] for the transparent huge pages.
+///
+/// The advices are specific to Linux; they are ignored on the other platforms.
+///
+/// [`CMmapAlloc`]: struct.CMmapAlloc.html
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum HugePages {
+    /// Follows the system setting. (Default.)
+    #[default]
+    Default,
+    /// Aligns allocations of [`HUGE_PAGE_SIZE`] or larger and advises `MADV_HUGEPAGE` .
+    ///
+    /// [`HUGE_PAGE_SIZE`]: constant.HUGE_PAGE_SIZE.html
+    Enabled,
+    /// Advises `MADV_NOHUGEPAGE` to opt out of the transparent huge pages.
+    Disabled,
+}
 
 impl Default for CMmapAlloc {
     fn default() -> Self {
@@ -267,69 +676,662 @@ impl Default for CMmapAlloc {
 }
 
 impl CMmapAlloc {
-    /// Creates a new instance.
+    /// Creates a new instance charging to the default pool.
     #[inline]
     pub const fn new() -> Self {
-        Self(mmap_allocator::MmapAllocator)
+        Self::with_pool(&DEFAULT_POOL)
+    }
+
+    /// Creates a new instance charging to `pool` .
+    #[inline]
+    pub const fn with_pool(pool: &'static CachePool) -> Self {
+        Self {
+            alloc: mmap_allocator::MmapAllocator,
+            pool,
+            slab: false,
+            huge_pages: HugePages::Default,
+            dont_dump: false,
+            mlock: false,
+            #[cfg(feature = "guard_pages")]
+            guard_pages: GuardPages::Disabled,
+        }
+    }
+
+    /// Enables or disables the slabs for small allocations. (Disabled by default.)
+    ///
+    /// See [the struct document] for details.
+    ///
+    /// [the struct document]: #slabs
+    #[inline]
+    pub const fn slab(mut self, enabled: bool) -> Self {
+        self.slab = enabled;
+        self
+    }
+
+    /// Sets the policy for the transparent huge pages. (`HugePages::Default` by default.)
+    ///
+    /// See [the struct document] for details.
+    ///
+    /// [the struct document]: #huge-pages
+    #[inline]
+    pub const fn huge_pages(mut self, policy: HugePages) -> Self {
+        self.huge_pages = policy;
+        self
+    }
+
+    /// Excludes the memory from core dumps if `enabled` . (Disabled by default.)
+    ///
+    /// It applies `madvise(MADV_DONTDUMP)` to each mapping on Linux, and does nothing on the
+    /// other platforms. Feature `dont_dump` applies it to [`CAlloc`] and [`CPoolAlloc`] as well.
+    ///
+    /// [`CAlloc`]: struct.CAlloc.html
+    /// [`CPoolAlloc`]: struct.CPoolAlloc.html
+    #[inline]
+    pub const fn dont_dump(mut self, enabled: bool) -> Self {
+        self.dont_dump = enabled;
+        self
+    }
+
+    /// Locks the memory in RAM (`mlock` ) if `enabled` , so that it is never swapped out.
+    /// (Disabled by default.)
+    ///
+    /// [`CachePool::set_mlock`] enables it for all the `CMmapAlloc` charging to the pool.
+    ///
+    /// If `mlock` fails, the allocation fails and [`CachePool::take_lock_error`] returns the
+    /// error. [`CachePool::locked_size`] returns how many bytes are locked.
+    ///
+    /// The memory is unlocked when it is unmapped.
+    ///
+    /// [`CachePool::set_mlock`]: struct.CachePool.html#method.set_mlock
+    /// [`CachePool::take_lock_error`]: struct.CachePool.html#method.take_lock_error
+    /// [`CachePool::locked_size`]: struct.CachePool.html#method.locked_size
+    #[inline]
+    pub const fn mlock(mut self, enabled: bool) -> Self {
+        self.mlock = enabled;
+        self
+    }
+
+    /// Places an inaccessible page (`PROT_NONE` ) after or before each allocation, so that
+    /// a buffer overrun causes a segmentation fault immediately. (Disabled by default.)
+    ///
+    /// With `GuardPages::After` , each object is aligned to the end of the pages to detect
+    /// overruns. (Less than `Layout::align()` bytes can be left accessible after the object
+    /// because of the alignment.) With `GuardPages::Before` , each object starts at the
+    /// beginning of the pages to detect underruns.
+    ///
+    /// The guard pages are not charged to the pool; [`CachePool::guard_size`] reports them.
+    /// The slabs and the huge pages are disabled while the guard pages are enabled.
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CMmapAlloc, CachePool, GuardPages};
+    /// use std::alloc::{GlobalAlloc, Layout};
+    ///
+    /// static POOL: CachePool = CachePool::new();
+    /// let alloc = CMmapAlloc::with_pool(&POOL).guard_pages(GuardPages::After);
+    ///
+    /// let page_size = mmap_allocator::page_size();
+    /// let layout = Layout::new::<[u8; 100]>();
+    /// unsafe {
+    ///     let ptr = alloc.alloc(layout);
+    ///     assert_eq!(0, (ptr as usize + 100) % page_size);
+    ///     assert_eq!(page_size, POOL.cache_size());
+    ///     assert_eq!(page_size, POOL.guard_size());
+    ///
+    ///     alloc.dealloc(ptr, layout);
+    ///     assert_eq!(0, POOL.guard_size());
+    /// }
+    /// ```
+    ///
+    /// [`CachePool::guard_size`]: struct.CachePool.html#method.guard_size
+    #[cfg(feature = "guard_pages")]
+    #[inline]
+    pub const fn guard_pages(mut self, placement: GuardPages) -> Self {
+        self.guard_pages = placement;
+        self
+    }
+
+    /// Returns the pool which `self` charges to.
+    #[inline]
+    pub fn pool(&self) -> &'static CachePool {
+        self.pool
+    }
+
+    /// Marks the memory of `ptr` purgeable, and returns the token to use it again.
+    ///
+    /// The kernel may discard the purgeable memory under memory pressure (`MADV_FREE` on Linux)
+    /// instead of swapping it out or killing the process. The memory is still charged to the
+    /// pool; [`CachePool::purgeable_size`] tells how many bytes are purgeable.
+    ///
+    /// Call [`Purged::reclaim`] before using the memory again. It returns `false` if the contents
+    /// have been discarded.
+    ///
+    /// Allocations in the slabs share pages with others, so they are never purged.
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CMmapAlloc, CachePool};
+    /// use std::alloc::{GlobalAlloc, Layout};
+    ///
+    /// static POOL: CachePool = CachePool::new();
+    /// let alloc = CMmapAlloc::with_pool(&POOL);
+    ///
+    /// let layout = Layout::new::<[u8; 65536]>();
+    /// unsafe {
+    ///     let ptr = alloc.alloc(layout);
+    ///     *ptr = 42;
+    ///
+    ///     let purged = alloc.purge(ptr, layout);
+    ///     assert_eq!(purged.len(), POOL.purgeable_size());
+    ///
+    ///     if purged.reclaim() {
+    ///         assert_eq!(42, *ptr);
+    ///     }
+    ///     assert_eq!(0, POOL.purgeable_size());
+    ///
+    ///     alloc.dealloc(ptr, layout);
+    /// }
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `self` allocated with `layout` . The memory must not be accessed
+    /// nor deallocated until the returned token is reclaimed or dropped.
+    ///
+    /// [`CachePool::purgeable_size`]: struct.CachePool.html#method.purgeable_size
+    /// [`Purged::reclaim`]: struct.Purged.html#method.reclaim
+    pub unsafe fn purge(&self, ptr: *mut u8, layout: Layout) -> Purged {
+        let len = match self.slab_class(layout) {
+            Some(_) => 0,
+            None => self.mapping_size(layout.size()),
+        };
+        Purged::new(self.region(ptr), len, self.pool)
     }
 }
 
-unsafe impl GlobalAlloc for CMmapAlloc {
+impl CMmapAlloc {
+    /// Returns the size class of the slabs for `layout` , or `None` if `layout` is not
+    /// allocated from the slabs.
     #[inline]
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let ptr = self.0.alloc(layout);
+    pub(crate) fn slab_class(&self, layout: Layout) -> Option<usize> {
+        if self.slab && !self.guarded() {
+            slab::class_of(layout)
+        } else {
+            None
+        }
+    }
+
+    /// Takes a slot of `class` from the slabs with the attributes of `self` .
+    #[inline]
+    unsafe fn slab_alloc(&self, class: usize) -> *mut u8 {
+        let attrs = slab::Attrs {
+            locked: self.locks(),
+            dont_dump: self.dont_dump,
+        };
+
+        self.pool.slabs().alloc(attrs, class, || {
+            let layout = slab::page_layout();
+            if !self.pool.try_increase_size(layout.size()) {
+                return ptr::null_mut();
+            }
+
+            let page = self.alloc.alloc(layout);
+            if page.is_null() {
+                self.pool.decrease_size(layout.size());
+                return ptr::null_mut();
+            }
+
+            if !self.commit(page, layout.size(), attrs.locked) {
+                os::unmap(page, layout.size());
+                self.pool.decrease_size(layout.size());
+                return ptr::null_mut();
+            }
+
+            if attrs.locked {
+                self.pool.add_locked(layout.size());
+            }
+            page
+        })
+    }
 
-        if !ptr.is_null() {
-            let allocating = (layout.size() + mmap_allocator::page_size() - 1)
-                / mmap_allocator::page_size()
-                * mmap_allocator::page_size();
-            SIZE_ALLOC.increase_size(allocating);
+    /// Returns `ptr` to the slabs, and releases the slab page if it gets empty.
+    ///
+    /// The page is accounted by its own attributes, which can differ from those of `self` .
+    #[inline]
+    unsafe fn slab_dealloc(&self, class: usize, ptr: *mut u8) {
+        if let Some((page, attrs)) = self.pool.slabs().dealloc(class, ptr) {
+            let layout = slab::page_layout();
+            os::unmap(page, layout.size());
+            self.pool.decrease_size(layout.size());
+
+            if attrs.locked {
+                self.pool.sub_locked(layout.size());
+            }
+        }
+    }
+
+    /// Same to `alloc` except for this method does not call the shrinkers.
+    #[inline]
+    pub(crate) unsafe fn alloc_once(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = self.slab_class(layout) {
+            return self.slab_alloc(class);
+        }
+
+        let allocating = self.mapping_size(layout.size());
+        if !self.pool.try_increase_size(allocating) {
+            return ptr::null_mut();
+        }
+
+        let ptr = self.map(layout);
+        if ptr.is_null() {
+            self.pool.decrease_size(allocating);
+        } else if self.locks() {
+            self.pool.add_locked(allocating);
         }
 
         ptr
     }
 
+    /// Same to `alloc_zeroed` except for this method does not call the shrinkers.
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        debug_assert_eq!(false, ptr.is_null());
-        self.0.dealloc(ptr, layout);
+    pub(crate) unsafe fn alloc_zeroed_once(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = self.slab_class(layout) {
+            // The slot may have been used before.
+            let ptr = self.slab_alloc(class);
+            if !ptr.is_null() {
+                ptr::write_bytes(ptr, 0, layout.size());
+            }
+            return ptr;
+        }
+
+        let allocating = self.mapping_size(layout.size());
+        if !self.pool.try_increase_size(allocating) {
+            return ptr::null_mut();
+        }
 
-        let deallocating = (layout.size() + mmap_allocator::page_size() - 1)
-            / mmap_allocator::page_size()
-            * mmap_allocator::page_size();
-        SIZE_ALLOC.decrease_size(deallocating);
+        // Mapped memory is always zeroed.
+        let ptr = self.map(layout);
+        if ptr.is_null() {
+            self.pool.decrease_size(allocating);
+        } else if self.locks() {
+            self.pool.add_locked(allocating);
+        }
+
+        ptr
     }
 
+    /// Same to `dealloc` except for this method does not update the statistics.
     #[inline]
-    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        let ptr = self.0.realloc(ptr, layout, new_size);
+    pub(crate) unsafe fn dealloc_once(&self, ptr: *mut u8, layout: Layout) {
+        debug_assert!(!ptr.is_null());
+
+        if let Some(class) = self.slab_class(layout) {
+            self.slab_dealloc(class, ptr);
+            return;
+        }
+
+        let deallocating = self.mapping_size(layout.size());
+
+        #[cfg(feature = "guard_pages")]
+        if self.guarded() {
+            self.unmap_guarded(ptr, deallocating);
+        } else {
+            os::unmap(ptr, deallocating);
+        }
+        #[cfg(not(feature = "guard_pages"))]
+        os::unmap(ptr, deallocating);
+
+        self.pool.decrease_size(deallocating);
+
+        if self.locks() {
+            self.pool.sub_locked(deallocating);
+        }
+    }
+
+    /// Same to `realloc` except for this method does not call the shrinkers.
+    #[inline]
+    pub(crate) unsafe fn realloc_once(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let class = self.slab_class(layout);
+        let new_class = self.slab_class(new_layout);
+
+        if class.is_some() && class == new_class {
+            return ptr;
+        }
+
+        if class.is_some() || new_class.is_some() || self.guarded() {
+            let ptr_ = self.alloc_once(new_layout);
+            if !ptr_.is_null() {
+                ptr::copy_nonoverlapping(ptr, ptr_, cmp::min(layout.size(), new_size));
+                self.dealloc_once(ptr, layout);
+            }
+            return ptr_;
+        }
+
+        let allocating = self.mapping_size(new_size);
+        let deallocating = self.mapping_size(layout.size());
+
+        if deallocating < allocating {
+            if !self.pool.try_increase_size(allocating - deallocating) {
+                return ptr::null_mut();
+            }
+
+            let ptr = self.remap(ptr, layout, new_size);
+            if ptr.is_null() {
+                self.pool.decrease_size(allocating - deallocating);
+            } else if self.locks() {
+                self.pool.add_locked(allocating - deallocating);
+            }
+
+            ptr
+        } else {
+            let ptr = self.remap(ptr, layout, new_size);
+            if !ptr.is_null() {
+                self.pool.decrease_size(deallocating - allocating);
+
+                if self.locks() {
+                    self.pool.sub_locked(deallocating - allocating);
+                }
+            }
+
+            ptr
+        }
+    }
+}
+
+impl CMmapAlloc {
+    /// Returns whether an allocation of `size` bytes is aligned to `HUGE_PAGE_SIZE` or not.
+    #[inline]
+    fn is_huge(&self, size: usize) -> bool {
+        self.huge_pages == HugePages::Enabled && HUGE_PAGE_SIZE <= size && !self.guarded()
+    }
 
-        let allocating = (new_size + mmap_allocator::page_size() - 1) / mmap_allocator::page_size()
-            * mmap_allocator::page_size();
-        let deallocating = (layout.size() + mmap_allocator::page_size() - 1)
-            / mmap_allocator::page_size()
-            * mmap_allocator::page_size();
+    /// Returns whether `self` places the guard pages or not.
+    #[cfg(feature = "guard_pages")]
+    #[inline]
+    pub(crate) fn guarded(&self) -> bool {
+        self.guard_pages != GuardPages::Disabled
+    }
 
-        if allocating < deallocating {
-            SIZE_ALLOC.decrease_size(deallocating - allocating);
+    /// Returns `false` because feature `guard_pages` is disabled.
+    #[cfg(not(feature = "guard_pages"))]
+    #[inline]
+    pub(crate) fn guarded(&self) -> bool {
+        false
+    }
+
+    /// Returns the head of the pages of `ptr` excluding the guard page.
+    #[inline]
+    fn region(&self, ptr: *mut u8) -> *mut u8 {
+        if self.guarded() {
+            let page_size = mmap_allocator::page_size();
+            (ptr as usize / page_size * page_size) as *mut u8
         } else {
-            SIZE_ALLOC.increase_size(allocating - deallocating);
+            ptr
         }
+    }
 
+    /// Returns the byte size of the mapping for `size` bytes, which is charged to the pool.
+    #[inline]
+    pub(crate) fn mapping_size(&self, size: usize) -> usize {
+        if self.is_huge(size) {
+            size.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE
+        } else {
+            page_round(size)
+        }
+    }
+
+    /// Maps pages for `layout` without charging.
+    #[inline]
+    unsafe fn map(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "guard_pages")]
+        if self.guarded() {
+            return self.map_guarded(layout);
+        }
+
+        let len = self.mapping_size(layout.size());
+
+        let ptr = if self.is_huge(layout.size()) {
+            os::map_aligned(len, cmp::max(HUGE_PAGE_SIZE, layout.align()))
+        } else {
+            self.alloc.alloc(layout)
+        };
+
+        if !ptr.is_null() && !self.commit(ptr, len, self.locks()) {
+            os::unmap(ptr, len);
+            return ptr::null_mut();
+        }
         ptr
     }
 
+    /// Maps pages for `layout` and the guard page without charging.
+    #[cfg(feature = "guard_pages")]
+    unsafe fn map_guarded(&self, layout: Layout) -> *mut u8 {
+        let page_size = mmap_allocator::page_size();
+        if page_size < layout.align() {
+            return ptr::null_mut();
+        }
+
+        let len = self.mapping_size(layout.size());
+        let total = match len.checked_add(page_size) {
+            None => return ptr::null_mut(),
+            Some(t) => t,
+        };
+
+        let base = os::map_aligned(total, page_size);
+        if base.is_null() {
+            return ptr::null_mut();
+        }
+
+        let (region, guard) = match self.guard_pages {
+            GuardPages::Before => (base.add(page_size), base),
+            _ => (base, base.add(len)),
+        };
+        if !os::protect_none(guard, page_size) || !self.commit(region, len, self.locks()) {
+            os::unmap(base, total);
+            return ptr::null_mut();
+        }
+
+        self.pool.add_guard(page_size);
+        match self.guard_pages {
+            GuardPages::Before => region,
+            _ => region.add((len - layout.size()) & !(layout.align() - 1)),
+        }
+    }
+
+    /// Unmaps the pages of `ptr` of `len` bytes and the guard page.
+    #[cfg(feature = "guard_pages")]
+    unsafe fn unmap_guarded(&self, ptr: *mut u8, len: usize) {
+        let page_size = mmap_allocator::page_size();
+        let region = self.region(ptr);
+        let base = match self.guard_pages {
+            GuardPages::Before => region.sub(page_size),
+            _ => region,
+        };
+
+        os::unmap(base, len + page_size);
+        self.pool.sub_guard(page_size);
+    }
+
+    /// Applies the advices to the new mapping of `len` bytes from `ptr` , and locks it if
+    /// `locks` .
+    ///
+    /// Returns `false` if failed to lock the memory. (`mremap` keeps the advices and the lock,
+    /// so it is not necessary to call this method after that.)
     #[inline]
-    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        let ptr = self.0.alloc_zeroed(layout);
+    unsafe fn commit(&self, ptr: *mut u8, len: usize, locks: bool) -> bool {
+        match self.huge_pages {
+            HugePages::Enabled if self.is_huge(len) => os::advise_huge_pages(ptr, len, true),
+            HugePages::Disabled => os::advise_huge_pages(ptr, len, false),
+            _ => (),
+        }
+
+        if self.dont_dump {
+            os::advise_dont_dump(ptr, len);
+        }
 
-        if !ptr.is_null() {
-            let allocating = (layout.size() + mmap_allocator::page_size() - 1)
-                / mmap_allocator::page_size()
-                * mmap_allocator::page_size();
-            SIZE_ALLOC.increase_size(allocating);
+        if locks {
+            if let Err(e) = os::lock(ptr, len) {
+                self.pool.set_lock_error(e);
+                return false;
+            }
         }
 
+        true
+    }
+
+    /// Returns whether `self` locks the memory in RAM or not.
+    #[inline]
+    fn locks(&self) -> bool {
+        self.mlock || self.pool.mlock_enabled()
+    }
+
+    /// Resizes the pages of `ptr` to `new_size` without charging.
+    ///
+    /// `mremap` resizes the mapping without copying the data. It tries to resize the mapping in
+    /// place at first, and then allows the kernel to move it.
+    ///
+    /// The locked mapping keeps locked; if it fails to lock the growing pages, the error is
+    /// recorded to the pool.
+    #[cfg(target_os = "linux")]
+    #[inline]
+    unsafe fn remap(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let old_len = self.mapping_size(layout.size());
+        let new_len = self.mapping_size(new_size);
+        if old_len == new_len {
+            return ptr;
+        }
+
+        let addr = ptr as *mut c_void;
+        let huge = self.is_huge(new_size);
+
+        // The huge pages require the mapping to be aligned.
+        // (`usize::is_multiple_of` would raise the minimum supported rust version.)
+        #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+        let aligned = (ptr as usize) % HUGE_PAGE_SIZE == 0;
+        if !huge || aligned {
+            match libc::mremap(addr, old_len, new_len, 0) {
+                libc::MAP_FAILED => {
+                    if new_len < old_len {
+                        return ptr::null_mut();
+                    }
+                }
+                ret => {
+                    if huge {
+                        os::advise_huge_pages(ret as *mut u8, new_len, true);
+                    }
+                    return ret as *mut u8;
+                }
+            }
+        }
+
+        if huge {
+            // Moves the mapping to an aligned address.
+            let dst = os::map_aligned(new_len, cmp::max(HUGE_PAGE_SIZE, layout.align()));
+            if dst.is_null() {
+                return ptr::null_mut();
+            }
+
+            let flags = libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED;
+            match libc::mremap(addr, old_len, new_len, flags, dst as *mut c_void) {
+                libc::MAP_FAILED => {
+                    self.check_remap_error();
+                    os::unmap(dst, new_len);
+                    ptr::null_mut()
+                }
+                _ => {
+                    os::advise_huge_pages(dst, new_len, true);
+                    dst
+                }
+            }
+        } else {
+            match libc::mremap(addr, old_len, new_len, libc::MREMAP_MAYMOVE) {
+                libc::MAP_FAILED => {
+                    self.check_remap_error();
+                    ptr::null_mut()
+                }
+                ret => ret as *mut u8,
+            }
+        }
+    }
+
+    /// Records the error to the pool if the last `mremap` failed to lock the growing pages.
+    ///
+    /// (`mremap` fails with `EAGAIN` if the locked memory would exceed `RLIMIT_MEMLOCK` .)
+    #[cfg(target_os = "linux")]
+    #[inline]
+    fn check_remap_error(&self) {
+        let errno = std::io::Error::last_os_error().raw_os_error();
+        if self.locks() && errno == Some(libc::EAGAIN) {
+            self.pool.set_lock_error(LockError::Limit);
+        }
+    }
+
+    /// Resizes the pages of `ptr` to `new_size` without charging.
+    #[cfg(not(target_os = "linux"))]
+    #[inline]
+    unsafe fn remap(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if self.mapping_size(layout.size()) == self.mapping_size(new_size) {
+            return ptr;
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let ptr_ = self.map(new_layout);
+        if !ptr_.is_null() {
+            ptr::copy_nonoverlapping(ptr, ptr_, cmp::min(layout.size(), new_size));
+            os::unmap(ptr, self.mapping_size(layout.size()));
+        }
+
+        ptr_
+    }
+}
+
+unsafe impl GlobalAlloc for CMmapAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocating = self.mapping_size(layout.size());
+        let ptr = self
+            .pool
+            .retry_with_shrink(allocating, || self.alloc_once(layout));
+        self.pool.record_alloc(ptr, layout);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.pool.record_dealloc(layout);
+        self.dealloc_once(ptr, layout);
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let growing = self
+            .mapping_size(new_size)
+            .saturating_sub(self.mapping_size(layout.size()));
+        let ptr = self
+            .pool
+            .retry_with_shrink(growing, || self.realloc_once(ptr, layout, new_size));
+        self.pool.record_realloc(ptr, layout, new_size);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let allocating = self.mapping_size(layout.size());
+        let ptr = self
+            .pool
+            .retry_with_shrink(allocating, || self.alloc_zeroed_once(layout));
+        self.pool.record_alloc(ptr, layout);
         ptr
     }
 }
+
+/// Rounds `bytes` up to the multiple of the OS page size.
+#[inline]
+fn page_round(bytes: usize) -> usize {
+    let page_size = mmap_allocator::page_size();
+    bytes.div_ceil(page_size) * page_size
+}
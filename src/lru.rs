@@ -0,0 +1,524 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LRU cache evicting entries when the pool exceeds the budget.
+
+use crate::{CPoolAlloc, CachePool, CacheVec, TryReserveError};
+use core::alloc::{GlobalAlloc, Layout};
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::ptr;
+use std::collections::hash_map::RandomState;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    hash: u64,
+    // Neighbours in the LRU list. `prev` is more recently used.
+    prev: *mut Node<K, V>,
+    next: *mut Node<K, V>,
+    // Next node in the same bucket.
+    chain: *mut Node<K, V>,
+}
+
+/// Hash map which evicts the least recently used entries automatically whenever the
+/// [`cache_size`] of the pool exceeds the budget.
+///
+/// Both the entries and the hash table are allocated in the pool, so evicting entries reduces
+/// the cache size of the pool. (If `K` or `V` owns memory, it is charged to the pool only if
+/// it is allocated in the pool, e.g. [`CacheVec`] with [`CPoolAlloc`] .)
+///
+/// Note that the cache size of the pool includes the memory used by anything else charged to
+/// the pool, and that the entry just inserted is never evicted by the insertion itself.
+///
+/// ```
+/// use mouse_cache_alloc::{CachePool, LruCache};
+///
+/// static POOL: CachePool = CachePool::new();
+///
+/// let mut cache: LruCache<u64, [u8; 1024]> = LruCache::new(&POOL, 16 * 1024);
+/// for i in 0..100 {
+///     cache.insert(i, [0; 1024]);
+/// }
+///
+/// assert!(cache.len() < 16);
+/// assert!(POOL.cache_size() <= 16 * 1024);
+/// assert!(cache.get(&99).is_some());
+/// assert!(cache.get(&0).is_none());
+/// ```
+///
+/// [`cache_size`]: struct.CachePool.html#method.cache_size
+/// [`CacheVec`]: struct.CacheVec.html
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+pub struct LruCache<K, V, S = RandomState> {
+    alloc: CPoolAlloc,
+    budget: usize,
+    buckets: CacheVec<*mut Node<K, V>, CPoolAlloc>,
+    // The most recently used node.
+    head: *mut Node<K, V>,
+    // The least recently used node.
+    tail: *mut Node<K, V>,
+    len: usize,
+    hasher: S,
+}
+
+unsafe impl<K, V, S> Send for LruCache<K, V, S>
+where
+    K: Send,
+    V: Send,
+    S: Send,
+{
+}
+
+unsafe impl<K, V, S> Sync for LruCache<K, V, S>
+where
+    K: Sync,
+    V: Sync,
+    S: Sync,
+{
+}
+
+impl<K, V, S> Drop for LruCache<K, V, S> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K, V> LruCache<K, V, RandomState> {
+    /// Creates a new empty instance allocating memory in `pool` and evicting entries when
+    /// `pool.cache_size()` exceeds `budget` .
+    #[inline]
+    pub fn new(pool: &'static CachePool, budget: usize) -> Self {
+        Self::with_hasher(pool, budget, RandomState::new())
+    }
+}
+
+impl<K, V, S> LruCache<K, V, S> {
+    /// Creates a new empty instance using `hasher` .
+    ///
+    /// See [`new`] for details.
+    ///
+    /// [`new`]: #method.new
+    #[inline]
+    pub fn with_hasher(pool: &'static CachePool, budget: usize, hasher: S) -> Self {
+        let alloc = CPoolAlloc::new(pool);
+        Self {
+            alloc,
+            budget,
+            buckets: CacheVec::new_in(alloc),
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    /// Returns the pool which `self` allocates memory in.
+    #[inline]
+    pub fn pool(&self) -> &'static CachePool {
+        self.alloc.pool()
+    }
+
+    /// Returns the budget.
+    #[inline]
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Sets the budget and evicts entries if the cache size of the pool exceeds it.
+    #[inline]
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Returns the number of the entries.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if `self` has no entry.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Evicts the least recently used entries while the cache size of the pool exceeds the
+    /// budget, and returns the number of the evicted entries.
+    ///
+    /// The most recently used entry is not evicted.
+    pub fn evict(&mut self) -> usize {
+        let mut count = 0;
+        while 1 < self.len && self.budget < self.pool().cache_size() {
+            self.pop_lru();
+            count += 1;
+        }
+        count
+    }
+
+    /// Removes the least recently used entry and returns it, or `None` if `self` is empty.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        if self.tail.is_null() {
+            return None;
+        }
+
+        unsafe {
+            let node = self.tail;
+            self.unchain(node);
+            Some(self.free(node))
+        }
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) {
+        while self.pop_lru().is_some() {}
+    }
+
+    /// Returns an iterator visiting all the entries from the most recently used one.
+    #[inline]
+    pub fn iter(&self) -> LruIter<'_, K, V> {
+        LruIter {
+            node: self.head,
+            _cache: core::marker::PhantomData,
+        }
+    }
+
+    /// Removes `node` from the bucket chain and from the LRU list.
+    unsafe fn unchain(&mut self, node: *mut Node<K, V>) {
+        let index = self.bucket_index((*node).hash);
+        let mut p = &mut self.buckets[index] as *mut *mut Node<K, V>;
+        while *p != node {
+            p = &mut (**p).chain;
+        }
+        *p = (*node).chain;
+
+        self.unlink(node);
+    }
+
+    /// Removes `node` from the LRU list.
+    unsafe fn unlink(&mut self, node: *mut Node<K, V>) {
+        let prev = (*node).prev;
+        let next = (*node).next;
+
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            (*next).prev = prev;
+        }
+    }
+
+    /// Inserts `node` at the front of the LRU list.
+    unsafe fn push_front(&mut self, node: *mut Node<K, V>) {
+        (*node).prev = ptr::null_mut();
+        (*node).next = self.head;
+
+        if self.head.is_null() {
+            self.tail = node;
+        } else {
+            (*self.head).prev = node;
+        }
+        self.head = node;
+    }
+
+    /// Deallocates `node` and returns the key and the value.
+    unsafe fn free(&mut self, node: *mut Node<K, V>) -> (K, V) {
+        let Node { key, value, .. } = ptr::read(node);
+        self.alloc
+            .dealloc(node as *mut u8, Layout::new::<Node<K, V>>());
+        self.len -= 1;
+        (key, value)
+    }
+
+    #[inline]
+    fn bucket_index(&self, hash: u64) -> usize {
+        (hash as usize) & (self.buckets.len() - 1)
+    }
+}
+
+impl<K, V, S> LruCache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Returns a reference to the value corresponding to `key` and marks it as the most
+    /// recently used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let node = self.find(key);
+        if node.is_null() {
+            return None;
+        }
+
+        unsafe {
+            self.unlink(node);
+            self.push_front(node);
+            Some(&(*node).value)
+        }
+    }
+
+    /// Returns a mutable reference to the value corresponding to `key` and marks it as the most
+    /// recently used.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let node = self.find(key);
+        if node.is_null() {
+            return None;
+        }
+
+        unsafe {
+            self.unlink(node);
+            self.push_front(node);
+            Some(&mut (*node).value)
+        }
+    }
+
+    /// Returns a reference to the value corresponding to `key` without updating the LRU order.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let node = self.find(key);
+        if node.is_null() {
+            None
+        } else {
+            unsafe { Some(&(*node).value) }
+        }
+    }
+
+    /// Returns `true` if `self` has `key` .
+    /// (The LRU order is not updated.)
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        !self.find(key).is_null()
+    }
+
+    /// Inserts `key` and `value` as the most recently used entry, and evicts the least recently
+    /// used entries if the cache size of the pool exceeds the budget.
+    ///
+    /// If `self` already has `key` , replaces the value and returns the old one.
+    ///
+    /// Entries are evicted as well if the pool fails to allocate memory (e.g. the limit of the
+    /// pool is exceeded.)
+    ///
+    /// If the pool fails to allocate memory even after all the other entries are evicted, the
+    /// entry is dropped without being inserted. Use [`try_insert`] to know it.
+    ///
+    /// [`try_insert`]: #method.try_insert
+    #[inline]
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.try_insert(key, value).unwrap_or(None)
+    }
+
+    /// Same to [`insert`] except for this method returns an error if the pool fails to allocate
+    /// memory even after all the other entries are evicted.
+    ///
+    /// `key` and `value` are dropped on error.
+    ///
+    /// [`insert`]: #method.insert
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError> {
+        let node = self.find(&key);
+        if !node.is_null() {
+            unsafe {
+                self.unlink(node);
+                self.push_front(node);
+                let old = core::mem::replace(&mut (*node).value, value);
+                self.evict();
+                return Ok(Some(old));
+            }
+        }
+
+        self.reserve_bucket()?;
+
+        let layout = Layout::new::<Node<K, V>>();
+        let node = loop {
+            let ptr = unsafe { self.alloc.alloc(layout) } as *mut Node<K, V>;
+            if !ptr.is_null() {
+                break ptr;
+            }
+            if self.pop_lru().is_none() {
+                return Err(TryReserveError::AllocError(layout));
+            }
+        };
+
+        let hash = self.hash(&key);
+        let index = self.bucket_index(hash);
+
+        unsafe {
+            ptr::write(
+                node,
+                Node {
+                    key,
+                    value,
+                    hash,
+                    prev: ptr::null_mut(),
+                    next: ptr::null_mut(),
+                    chain: self.buckets[index],
+                },
+            );
+            self.buckets[index] = node;
+            self.push_front(node);
+        }
+        self.len += 1;
+
+        self.evict();
+        Ok(None)
+    }
+
+    /// Removes the entry corresponding to `key` and returns the value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let node = self.find(key);
+        if node.is_null() {
+            return None;
+        }
+
+        unsafe {
+            self.unchain(node);
+            Some(self.free(node).1)
+        }
+    }
+
+    /// Returns the node corresponding to `key` , or null.
+    fn find<Q>(&self, key: &Q) -> *mut Node<K, V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.len == 0 {
+            return ptr::null_mut();
+        }
+
+        let hash = self.hash(key);
+        let mut node = self.buckets[self.bucket_index(hash)];
+
+        unsafe {
+            while !node.is_null() {
+                if (*node).hash == hash && (*node).key.borrow() == key {
+                    break;
+                }
+                node = (*node).chain;
+            }
+        }
+
+        node
+    }
+
+    #[inline]
+    fn hash<Q>(&self, key: &Q) -> u64
+    where
+        Q: ?Sized + Hash,
+    {
+        self.hasher.hash_one(key)
+    }
+
+    /// Doubles the buckets if the number of the entries reaches it.
+    ///
+    /// If the pool fails to allocate the new buckets, keeps the current ones (i.e. the load
+    /// factor gets higher.) If there is no bucket yet, evicts entries and retries, and returns
+    /// an error if no entry is left.
+    fn reserve_bucket(&mut self) -> Result<(), TryReserveError> {
+        if self.len < self.buckets.len() {
+            return Ok(());
+        }
+
+        let new_len = (self.buckets.len() * 2).max(8);
+        let mut buckets = CacheVec::new_in(self.alloc);
+
+        while let Err(e) = buckets.try_reserve_exact(new_len) {
+            if !self.buckets.is_empty() {
+                return Ok(());
+            }
+            if self.pop_lru().is_none() {
+                return Err(e);
+            }
+        }
+
+        buckets.resize(new_len, ptr::null_mut());
+        self.buckets = buckets;
+
+        let mut node = self.head;
+        while !node.is_null() {
+            unsafe {
+                let index = self.bucket_index((*node).hash);
+                (*node).chain = self.buckets[index];
+                self.buckets[index] = node;
+                node = (*node).next;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over the entries of [`LruCache`] from the most recently used one.
+///
+/// [`LruCache`]: struct.LruCache.html
+pub struct LruIter<'a, K, V> {
+    node: *const Node<K, V>,
+    _cache: core::marker::PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> Iterator for LruIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node.is_null() {
+            return None;
+        }
+
+        unsafe {
+            let node = &*self.node;
+            self.node = node.next;
+            Some((&node.key, &node.value))
+        }
+    }
+}
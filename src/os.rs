@@ -0,0 +1,196 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Thin wrappers of the system calls for `CMmapAlloc` .
+
+use core::{fmt, ptr};
+use std::os::raw::{c_int, c_void};
+
+/// Maps `len` bytes of anonymous memory aligned to `align` , or returns null on failure.
+///
+/// Both `len` and `align` must be multiples of the OS page size.
+pub unsafe fn map_aligned(len: usize, align: usize) -> *mut u8 {
+    let page_size = mmap_allocator::page_size();
+    let total = match len.checked_add(align - page_size) {
+        None => return ptr::null_mut(),
+        Some(t) => t,
+    };
+
+    let base = libc::mmap(
+        ptr::null_mut(),
+        total,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if base == libc::MAP_FAILED {
+        return ptr::null_mut();
+    }
+
+    // Unmaps the surplus before and after the aligned region.
+    let base = base as usize;
+    let head = base.next_multiple_of(align) - base;
+    let tail = total - head - len;
+
+    if 0 < head {
+        libc::munmap(base as *mut c_void, head);
+    }
+    if 0 < tail {
+        libc::munmap((base + head + len) as *mut c_void, tail);
+    }
+
+    (base + head) as *mut u8
+}
+
+/// Unmaps `len` bytes from `ptr` .
+#[inline]
+pub unsafe fn unmap(ptr: *mut u8, len: usize) {
+    libc::munmap(ptr as *mut c_void, len);
+}
+
+/// Makes `len` bytes from `ptr` inaccessible. (`PROT_NONE` .)
+///
+/// Returns `false` on failure.
+#[cfg(feature = "guard_pages")]
+#[inline]
+pub unsafe fn protect_none(ptr: *mut u8, len: usize) -> bool {
+    libc::mprotect(ptr as *mut c_void, len, libc::PROT_NONE) == 0
+}
+
+/// Advises the kernel to back `len` bytes from `ptr` with transparent huge pages if `enabled` ,
+/// or not to if not `enabled` .
+///
+/// Errors are ignored because the advice is just a hint.
+#[cfg(target_os = "linux")]
+#[inline]
+pub unsafe fn advise_huge_pages(ptr: *mut u8, len: usize, enabled: bool) {
+    let advice = if enabled {
+        libc::MADV_HUGEPAGE
+    } else {
+        libc::MADV_NOHUGEPAGE
+    };
+    libc::madvise(ptr as *mut c_void, len, advice);
+}
+
+/// Does nothing because transparent huge pages are specific to Linux.
+#[cfg(not(target_os = "linux"))]
+#[inline]
+pub unsafe fn advise_huge_pages(_ptr: *mut u8, _len: usize, _enabled: bool) {}
+
+/// Advises the kernel that it may free `len` bytes from `ptr` lazily. (`MADV_FREE` .)
+///
+/// Returns `false` if the advice is not supported.
+#[cfg(target_os = "linux")]
+#[inline]
+pub unsafe fn advise_free(ptr: *mut u8, len: usize) -> bool {
+    libc::madvise(ptr as *mut c_void, len, libc::MADV_FREE) == 0
+}
+
+/// Returns `false` because `MADV_FREE` is not supported.
+#[cfg(not(target_os = "linux"))]
+#[inline]
+pub unsafe fn advise_free(_ptr: *mut u8, _len: usize) -> bool {
+    false
+}
+
+/// Advises the kernel to exclude `len` bytes from `ptr` from core dumps. (`MADV_DONTDUMP` .)
+///
+/// Errors are ignored because core dumps are not critical.
+#[cfg(target_os = "linux")]
+#[inline]
+pub unsafe fn advise_dont_dump(ptr: *mut u8, len: usize) {
+    libc::madvise(ptr as *mut c_void, len, libc::MADV_DONTDUMP);
+}
+
+/// Does nothing because `MADV_DONTDUMP` is specific to Linux.
+#[cfg(not(target_os = "linux"))]
+#[inline]
+pub unsafe fn advise_dont_dump(_ptr: *mut u8, _len: usize) {}
+
+/// Error of `mlock` .
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum LockError {
+    /// The locked memory would exceed `RLIMIT_MEMLOCK` , or the kernel is short of memory.
+    /// (`ENOMEM` or `EAGAIN` .)
+    Limit,
+    /// The process is not permitted to lock memory. (`EPERM` .)
+    Permission,
+    /// Other error with the `errno` .
+    Other(i32),
+}
+
+impl LockError {
+    /// Creates a new instance from `errno` .
+    #[inline]
+    pub(crate) fn from_errno(errno: c_int) -> Self {
+        match errno {
+            libc::ENOMEM | libc::EAGAIN => Self::Limit,
+            libc::EPERM => Self::Permission,
+            e => Self::Other(e),
+        }
+    }
+
+    /// Returns the `errno` of `self` .
+    #[inline]
+    pub fn errno(&self) -> i32 {
+        match *self {
+            Self::Limit => libc::ENOMEM,
+            Self::Permission => libc::EPERM,
+            Self::Other(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Limit => f.write_str("failed to lock memory: exceeded the limit"),
+            Self::Permission => f.write_str("failed to lock memory: permission denied"),
+            Self::Other(e) => write!(f, "failed to lock memory: errno {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+/// Locks `len` bytes from `ptr` in RAM. (`mlock` .)
+#[inline]
+pub unsafe fn lock(ptr: *mut u8, len: usize) -> Result<(), LockError> {
+    if libc::mlock(ptr as *const c_void, len) == 0 {
+        Ok(())
+    } else {
+        let errno = std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::ENOMEM);
+        Err(LockError::from_errno(errno))
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Poisoning and quarantine of the memory freed through `CAlloc` and `CPoolAlloc` .
+//!
+//! The freed memory is filled with [`POISON`] and kept in the quarantine for a while before
+//! being released actually. When the memory leaves the quarantine, it is checked whether the
+//! poison is intact; if not, somebody has written to the memory after freeing it.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::{cmp, fmt, mem, ptr};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// The byte filled in the freed memory.
+pub const POISON: u8 = 0xdf;
+
+/// The max number of the freed memory blocks kept in the quarantine.
+pub const QUARANTINE_LEN: usize = 256;
+
+/// Write to freed memory detected by feature `poison` .
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corruption {
+    /// The address of the freed memory.
+    pub address: usize,
+    /// The layout which the memory was allocated with.
+    pub layout: Layout,
+    /// The offset of the first byte which is not [`POISON`] .
+    ///
+    /// [`POISON`]: constant.POISON.html
+    pub offset: usize,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "use after free: {:#x} (size: {}, align: {}) was modified at offset {}",
+            self.address,
+            self.layout.size(),
+            self.layout.align(),
+            self.offset
+        )
+    }
+}
+
+/// Sets the function called when a [`Corruption`] is detected.
+///
+/// If `handler` is `None` (default,) the corruption is printed to stderr and the process is
+/// aborted. If `handler` returns, the memory is released as usual.
+///
+/// [`Corruption`]: struct.Corruption.html
+pub fn set_corruption_handler(handler: Option<fn(&Corruption)>) {
+    let handler = handler.map_or(ptr::null_mut(), |f| f as *mut ());
+    HANDLER.store(handler, Ordering::Release);
+}
+
+/// Checks and releases all the memory in the quarantine.
+pub fn flush_quarantine() {
+    let entries = {
+        let mut quarantine = QUARANTINE.lock().unwrap_or_else(|e| e.into_inner());
+        mem::replace(&mut *quarantine, Quarantine::EMPTY)
+    };
+
+    for entry in entries.iter() {
+        unsafe { release(entry.backend, entry.ptr, entry.layout()) };
+    }
+}
+
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine::EMPTY);
+
+/// Freed memory in the quarantine.
+#[derive(Clone, Copy)]
+struct Entry {
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    backend: &'static (dyn GlobalAlloc + Sync),
+}
+
+impl Entry {
+    #[inline]
+    fn layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.size, self.align) }
+    }
+}
+
+/// Ring buffer of the freed memory.
+struct Quarantine {
+    entries: [Option<Entry>; QUARANTINE_LEN],
+    head: usize,
+}
+
+// The pointers are accessed only while the quarantine is locked or after taken out of it.
+unsafe impl Send for Quarantine {}
+
+impl Quarantine {
+    const EMPTY: Self = Self {
+        entries: [None; QUARANTINE_LEN],
+        head: 0,
+    };
+
+    /// Pushes `entry` and returns the oldest entry if the quarantine is full.
+    #[inline]
+    fn push(&mut self, entry: Entry) -> Option<Entry> {
+        let evicted = self.entries[self.head].replace(entry);
+        self.head = (self.head + 1) % QUARANTINE_LEN;
+        evicted
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().flatten()
+    }
+}
+
+/// Poisons `ptr` and puts it into the quarantine instead of releasing through `backend` .
+///
+/// # Safety
+///
+/// `ptr` must be what `backend` allocated with `layout` , and must not have been deallocated
+/// yet.
+#[inline]
+pub unsafe fn dealloc(backend: &'static (dyn GlobalAlloc + Sync), ptr: *mut u8, layout: Layout) {
+    ptr::write_bytes(ptr, POISON, layout.size());
+
+    let entry = Entry {
+        ptr,
+        size: layout.size(),
+        align: layout.align(),
+        backend,
+    };
+    let evicted = {
+        let mut quarantine = QUARANTINE.lock().unwrap_or_else(|e| e.into_inner());
+        quarantine.push(entry)
+    };
+
+    if let Some(entry) = evicted {
+        release(entry.backend, entry.ptr, entry.layout());
+    }
+}
+
+/// Reallocates `ptr` through `backend` so that the old memory is always quarantined.
+///
+/// # Safety
+///
+/// `ptr` must be what `backend` allocated with `layout` , and must not have been deallocated
+/// yet.
+#[inline]
+pub unsafe fn realloc(
+    backend: &'static (dyn GlobalAlloc + Sync),
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let ptr_ = backend.alloc(new_layout);
+
+    if !ptr_.is_null() {
+        ptr::copy_nonoverlapping(ptr, ptr_, cmp::min(layout.size(), new_size));
+        dealloc(backend, ptr, layout);
+    }
+
+    ptr_
+}
+
+/// Checks the poison of `ptr` and releases it through `backend` .
+unsafe fn release(backend: &(dyn GlobalAlloc + Sync), ptr: *mut u8, layout: Layout) {
+    let bytes = core::slice::from_raw_parts(ptr, layout.size());
+    if let Some(offset) = bytes.iter().position(|&b| b != POISON) {
+        let corruption = Corruption {
+            address: ptr as usize,
+            layout,
+            offset,
+        };
+
+        let f = HANDLER.load(Ordering::Acquire);
+        if f.is_null() {
+            // Writes to `stderr` directly; `eprintln` can be captured, e.g. by the test harness,
+            // and the message would be lost by the abort.
+            let _ = writeln!(io::stderr(), "{}", corruption);
+            std::process::abort();
+        } else {
+            let f: fn(&Corruption) = mem::transmute(f);
+            f(&corruption);
+        }
+    }
+
+    backend.dealloc(ptr, layout);
+}
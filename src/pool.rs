@@ -0,0 +1,840 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache memory pools.
+
+use crate::os::LockError;
+#[cfg(feature = "registry")]
+use crate::registry::LiveAllocation;
+use crate::shard::Shards;
+use crate::shrinker::shrink;
+use crate::slab::Slabs;
+#[cfg(feature = "histogram")]
+use crate::stats::SizeHistogram;
+use crate::stats::{CacheStats, Counters};
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicUsize, Ordering};
+
+/// `CachePool` is a set of the cache memory counter, the limit, and the watermarks.
+///
+/// Each pool is independent of each other, so that the caches of different subsystems can be
+/// measured and limited separately.
+/// The crate functions like [`cache_size`] are for the default pool, which [`CAlloc`] and
+/// [`CMmapAlloc::new`] charge to.
+/// To charge another pool, use [`CPoolAlloc`] or [`CMmapAlloc::with_pool`] .
+///
+/// `CachePool` is usually declared as a static variable.
+///
+/// ```
+/// use mouse_cache_alloc::{CachePool, CPoolAlloc};
+/// use std::alloc::{GlobalAlloc, Layout};
+///
+/// static QUERY_CACHE: CachePool = CachePool::new();
+///
+/// let alloc = CPoolAlloc::new(&QUERY_CACHE);
+/// let layout = Layout::new::<[u8; 64]>();
+///
+/// unsafe {
+///     let ptr = alloc.alloc(layout);
+///     assert!(64 <= QUERY_CACHE.cache_size());
+///
+///     alloc.dealloc(ptr, layout);
+///     assert_eq!(0, QUERY_CACHE.cache_size());
+/// }
+/// ```
+///
+/// [`cache_size`]: fn.cache_size.html
+/// [`CAlloc`]: struct.CAlloc.html
+/// [`CMmapAlloc::new`]: struct.CMmapAlloc.html#method.new
+/// [`CPoolAlloc`]: struct.CPoolAlloc.html
+/// [`CMmapAlloc::with_pool`]: struct.CMmapAlloc.html#method.with_pool
+pub struct CachePool {
+    size: AtomicUsize,
+    limit: AtomicUsize,
+    high_watermark: AtomicUsize,
+    low_watermark: AtomicUsize,
+    under_pressure: AtomicBool,
+    on_high: AtomicPtr<()>,
+    on_low: AtomicPtr<()>,
+    parent: Option<&'static CachePool>,
+    batch: AtomicUsize,
+    shards: Shards,
+    peak: AtomicUsize,
+    counters: Counters,
+    slabs: Slabs,
+    purgeable: AtomicUsize,
+    mlock: AtomicBool,
+    locked: AtomicUsize,
+    lock_error: AtomicI32,
+    #[cfg(feature = "guard_pages")]
+    guard: AtomicUsize,
+}
+
+impl Default for CachePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CachePool {
+    /// Creates a new instance with no cache memory and no limit.
+    #[inline]
+    pub const fn new() -> Self {
+        Self::new_(None)
+    }
+
+    /// Creates a new instance with no cache memory and no limit, which is a child of `parent` .
+    ///
+    /// Memory charged to the child is charged to `parent` (and to all the ancestors) as well,
+    /// and an allocation fails if it would exceed the limit of any pool along the chain.
+    /// (i.e. the tightest limit wins.)
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CachePool, CPoolAlloc};
+    /// use std::alloc::{GlobalAlloc, Layout};
+    ///
+    /// static PROCESS: CachePool = CachePool::new();
+    /// static TENANT_A: CachePool = CachePool::with_parent(&PROCESS);
+    /// static TENANT_B: CachePool = CachePool::with_parent(&PROCESS);
+    ///
+    /// PROCESS.set_limit(1024 * 1024);
+    /// TENANT_A.set_limit(512 * 1024);
+    ///
+    /// let layout = Layout::new::<[u8; 1024]>();
+    /// unsafe {
+    ///     let ptr = CPoolAlloc::new(&TENANT_A).alloc(layout);
+    ///     assert_eq!(TENANT_A.cache_size(), PROCESS.cache_size());
+    ///     assert_eq!(0, TENANT_B.cache_size());
+    ///
+    ///     CPoolAlloc::new(&TENANT_A).dealloc(ptr, layout);
+    /// }
+    /// ```
+    #[inline]
+    pub const fn with_parent(parent: &'static CachePool) -> Self {
+        Self::new_(Some(parent))
+    }
+
+    #[inline]
+    const fn new_(parent: Option<&'static CachePool>) -> Self {
+        Self {
+            size: AtomicUsize::new(0),
+            limit: AtomicUsize::new(usize::MAX),
+            high_watermark: AtomicUsize::new(usize::MAX),
+            low_watermark: AtomicUsize::new(0),
+            under_pressure: AtomicBool::new(false),
+            on_high: AtomicPtr::new(ptr::null_mut()),
+            on_low: AtomicPtr::new(ptr::null_mut()),
+            parent,
+            batch: AtomicUsize::new(0),
+            shards: Shards::new(),
+            peak: AtomicUsize::new(0),
+            counters: Counters::new(),
+            slabs: Slabs::new(),
+            purgeable: AtomicUsize::new(0),
+            mlock: AtomicBool::new(false),
+            locked: AtomicUsize::new(0),
+            lock_error: AtomicI32::new(0),
+            #[cfg(feature = "guard_pages")]
+            guard: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the parent pool if any.
+    #[inline]
+    pub fn parent(&self) -> Option<&'static CachePool> {
+        self.parent
+    }
+
+    /// Returns the upper limit of the cache memory size.
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if `self` or any ancestor has the limit.
+    #[inline]
+    pub fn is_limited(&self) -> bool {
+        self.limit() != usize::MAX || self.parent.is_some_and(|p| p.is_limited())
+    }
+
+    /// Sets the upper limit of the cache memory size.
+    ///
+    /// See [`set_cache_limit`] for details.
+    ///
+    /// [`set_cache_limit`]: fn.set_cache_limit.html
+    #[inline]
+    pub fn set_limit(&self, bytes: usize) {
+        self.limit.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the high and the low watermarks.
+    #[inline]
+    pub fn watermarks(&self) -> (usize, usize) {
+        (
+            self.high_watermark.load(Ordering::Relaxed),
+            self.low_watermark.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Sets the high and the low watermarks.
+    ///
+    /// See [`set_cache_watermarks`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `high` is less than `low` .
+    ///
+    /// [`set_cache_watermarks`]: fn.set_cache_watermarks.html
+    #[inline]
+    pub fn set_watermarks(&self, high: usize, low: usize) {
+        assert!(low <= high);
+
+        self.high_watermark.store(high, Ordering::Relaxed);
+        self.low_watermark.store(low, Ordering::Relaxed);
+    }
+
+    /// Sets the callbacks called when the cache memory size crosses the watermarks.
+    ///
+    /// See [`set_pressure_callbacks`] for details.
+    ///
+    /// [`set_pressure_callbacks`]: fn.set_pressure_callbacks.html
+    #[inline]
+    pub fn set_pressure_callbacks(&self, on_high: Option<fn(usize)>, on_low: Option<fn(usize)>) {
+        let on_high = on_high.map_or(ptr::null_mut(), |f| f as *mut ());
+        let on_low = on_low.map_or(ptr::null_mut(), |f| f as *mut ());
+
+        self.on_high.store(on_high, Ordering::Release);
+        self.on_low.store(on_low, Ordering::Release);
+    }
+
+    /// Returns `true` if the cache memory size has crossed the high watermark and not fallen
+    /// under the low watermark yet.
+    #[inline]
+    pub fn is_under_pressure(&self) -> bool {
+        self.under_pressure.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many bytes memory is allocated for cache.
+    ///
+    /// If the shard batch is set, the result includes the credits of the shards, which are
+    /// charged but not used yet. (The error is less than or equals to `2 * SHARDS * batch` .)
+    /// Call [`cache_size_exact`] for the exact size.
+    ///
+    /// See [`set_shard_batch`] for details.
+    ///
+    /// [`cache_size_exact`]: #method.cache_size_exact
+    /// [`set_shard_batch`]: #method.set_shard_batch
+    #[inline]
+    pub fn cache_size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many bytes memory is allocated for cache excluding the credits of the
+    /// shards of `self` .
+    ///
+    /// The credits of the shards of the descendants are still included, because they are
+    /// charged to `self` as well. (The credits of a descendant are the difference between its
+    /// `cache_size` and `cache_size_exact` .)
+    ///
+    /// This method is slower than [`cache_size`] because it sums up all the shards.
+    ///
+    /// [`cache_size`]: #method.cache_size
+    #[inline]
+    pub fn cache_size_exact(&self) -> usize {
+        self.cache_size().saturating_sub(self.shards.sum())
+    }
+
+    /// Returns how many bytes of [`cache_size`] are purgeable; i.e. the kernel may discard them
+    /// under memory pressure. (See `CMmapAlloc::purge` .)
+    ///
+    /// The rest of `cache_size` is committed.
+    ///
+    /// [`cache_size`]: #method.cache_size
+    #[inline]
+    pub fn purgeable_size(&self) -> usize {
+        self.purgeable.load(Ordering::Relaxed)
+    }
+
+    /// Increases the purgeable size of `self` and all the ancestors by `bytes` .
+    #[inline]
+    pub(crate) fn add_purgeable(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.purgeable.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Decreases the purgeable size of `self` and all the ancestors by `bytes` .
+    #[inline]
+    pub(crate) fn sub_purgeable(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.purgeable.fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether `CMmapAlloc` locks the memory charged to `self` in RAM or not.
+    ///
+    /// See [`set_mlock`] for details.
+    ///
+    /// [`set_mlock`]: #method.set_mlock
+    #[inline]
+    pub fn mlock_enabled(&self) -> bool {
+        self.mlock.load(Ordering::Relaxed)
+    }
+
+    /// Makes `CMmapAlloc` lock the memory charged to `self` in RAM (`mlock` ) if `enabled` , as
+    /// `CMmapAlloc::mlock` does. (Disabled by default.)
+    ///
+    /// The setting must not be changed while memory allocated by `CMmapAlloc` is charged to
+    /// `self` , or [`locked_size`] gets wrong.
+    ///
+    /// [`locked_size`]: #method.locked_size
+    #[inline]
+    pub fn set_mlock(&self, enabled: bool) {
+        self.mlock.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns how many bytes of [`cache_size`] are locked in RAM.
+    ///
+    /// [`cache_size`]: #method.cache_size
+    #[inline]
+    pub fn locked_size(&self) -> usize {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Returns the last error of `mlock` and clears it, or `None` if no error has occurred since
+    /// the last call.
+    ///
+    /// `CMmapAlloc` fails to allocate memory if it fails to lock the memory.
+    #[inline]
+    pub fn take_lock_error(&self) -> Option<LockError> {
+        match self.lock_error.swap(0, Ordering::Relaxed) {
+            0 => None,
+            errno => Some(LockError::from_errno(errno)),
+        }
+    }
+
+    /// Records `error` to be returned from `take_lock_error` .
+    #[inline]
+    pub(crate) fn set_lock_error(&self, error: LockError) {
+        self.lock_error.store(error.errno(), Ordering::Relaxed);
+    }
+
+    /// Increases the locked size of `self` and all the ancestors by `bytes` .
+    #[inline]
+    pub(crate) fn add_locked(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.locked.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Decreases the locked size of `self` and all the ancestors by `bytes` .
+    #[inline]
+    pub(crate) fn sub_locked(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.locked.fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns how many bytes the guard pages of `CMmapAlloc` occupy for the memory charged to
+    /// `self` . (The guard pages are not charged.)
+    ///
+    /// See `CMmapAlloc::guard_pages` for details.
+    #[cfg(feature = "guard_pages")]
+    #[inline]
+    pub fn guard_size(&self) -> usize {
+        self.guard.load(Ordering::Relaxed)
+    }
+
+    /// Increases the guard size of `self` and all the ancestors by `bytes` .
+    #[cfg(feature = "guard_pages")]
+    #[inline]
+    pub(crate) fn add_guard(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.guard.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Decreases the guard size of `self` and all the ancestors by `bytes` .
+    #[cfg(feature = "guard_pages")]
+    #[inline]
+    pub(crate) fn sub_guard(&self, bytes: usize) {
+        for pool in self.chain() {
+            pool.guard.fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the iterator over `self` and all the ancestors.
+    #[inline]
+    fn chain(&self) -> impl Iterator<Item = &CachePool> {
+        core::iter::successors(Some(self), |pool| pool.parent)
+    }
+
+    /// Returns the snapshot of the allocation statistics.
+    ///
+    /// The statistics are maintained by the allocators of this crate; [`increase_size`] and
+    /// [`decrease_size`] only change `charged_bytes` and `peak_bytes` .
+    ///
+    /// The statistics of a parent pool include those of the children, as the charged bytes
+    /// do.
+    ///
+    /// [`increase_size`]: #method.increase_size
+    /// [`decrease_size`]: #method.decrease_size
+    pub fn stats(&self) -> CacheStats {
+        let mut ret = self.counters.sum();
+        ret.charged_bytes = self.cache_size_exact();
+        ret.peak_bytes = self.peak.load(Ordering::Relaxed);
+        ret
+    }
+
+    /// Returns the snapshot of the histogram of the live allocation sizes.
+    ///
+    /// The histogram of a parent pool includes those of the children.
+    ///
+    /// This method is available only if feature `histogram` is enabled.
+    #[cfg(feature = "histogram")]
+    #[inline]
+    pub fn histogram(&self) -> SizeHistogram {
+        self.counters.histogram()
+    }
+
+    /// Returns the allocations charged to `self` or the descendants and not deallocated yet.
+    ///
+    /// The registry records the allocations through [`CAlloc`] , [`CPoolAlloc`] , and
+    /// [`Counted`] ; the other allocators and [`increase_size`] are not registered.
+    ///
+    /// This method is available only if feature `registry` is enabled.
+    ///
+    /// ```
+    /// use mouse_cache_alloc::{CPoolAlloc, CachePool};
+    /// use std::alloc::{GlobalAlloc, Layout};
+    ///
+    /// static POOL: CachePool = CachePool::new();
+    /// let alloc = CPoolAlloc::new(&POOL);
+    ///
+    /// let layout = Layout::new::<[u8; 100]>();
+    /// unsafe {
+    ///     let ptr = alloc.alloc(layout);
+    ///
+    ///     let lives = POOL.live_allocations();
+    ///     assert_eq!(1, lives.len());
+    ///     assert_eq!(ptr as usize, lives[0].address);
+    ///     assert_eq!(POOL.cache_size_exact(), POOL.registered_size());
+    ///
+    ///     alloc.dealloc(ptr, layout);
+    ///     assert!(POOL.live_allocations().is_empty());
+    /// }
+    /// ```
+    ///
+    /// [`CAlloc`]: struct.CAlloc.html
+    /// [`CPoolAlloc`]: struct.CPoolAlloc.html
+    /// [`Counted`]: struct.Counted.html
+    /// [`increase_size`]: #method.increase_size
+    #[cfg(feature = "registry")]
+    pub fn live_allocations(&self) -> Vec<LiveAllocation> {
+        crate::registry::live(|pool| pool.chain().any(|p| ptr::eq(p, self)))
+    }
+
+    /// Returns the sum of the bytes charged for [`live_allocations`] .
+    ///
+    /// This equals to [`cache_size_exact`] unless some memory is charged without the registry.
+    ///
+    /// This method is available only if feature `registry` is enabled.
+    ///
+    /// [`live_allocations`]: #method.live_allocations
+    /// [`cache_size_exact`]: #method.cache_size_exact
+    #[cfg(feature = "registry")]
+    pub fn registered_size(&self) -> usize {
+        crate::registry::charged(|pool| pool.chain().any(|p| ptr::eq(p, self)))
+    }
+
+    /// Writes the report of [`live_allocations`] into `w` , including the difference between
+    /// [`registered_size`] and [`cache_size_exact`] .
+    ///
+    /// It is useful to find the leaked memory at shutdown.
+    ///
+    /// This method is available only if feature `registry` is enabled.
+    ///
+    /// [`live_allocations`]: #method.live_allocations
+    /// [`registered_size`]: #method.registered_size
+    /// [`cache_size_exact`]: #method.cache_size_exact
+    #[cfg(feature = "registry")]
+    pub fn write_leak_report<W>(&self, w: W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let lives = self.live_allocations();
+        crate::registry::write_report(w, &lives, self.cache_size_exact())
+    }
+
+    /// Resets `peak_bytes` of the statistics to the current cache size.
+    #[inline]
+    pub fn reset_peak(&self) {
+        self.peak.store(self.cache_size(), Ordering::Relaxed);
+    }
+
+    /// Updates the statistics of `self` and all the ancestors after allocating `ptr` with
+    /// `layout` . (`ptr` can be null.)
+    #[inline]
+    pub(crate) fn record_alloc(&self, ptr: *mut u8, layout: Layout) {
+        for pool in self.chain() {
+            if ptr.is_null() {
+                pool.counters.failure();
+            } else {
+                pool.counters.alloc(layout.size());
+            }
+        }
+    }
+
+    /// Updates the statistics of `self` and all the ancestors after reallocating memory of
+    /// `layout` to `new_size` , and returned `ptr` . (`ptr` can be null.)
+    #[inline]
+    pub(crate) fn record_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) {
+        for pool in self.chain() {
+            if ptr.is_null() {
+                pool.counters.failure();
+            } else {
+                pool.counters.realloc(layout.size(), new_size);
+            }
+        }
+    }
+
+    /// Updates the statistics of `self` and all the ancestors to deallocate memory of
+    /// `layout` .
+    #[inline]
+    pub(crate) fn record_dealloc(&self, layout: Layout) {
+        for pool in self.chain() {
+            pool.counters.dealloc(layout.size());
+        }
+    }
+
+    /// Returns the slabs of `CMmapAlloc` charged to `self` .
+    #[inline]
+    pub(crate) fn slabs(&self) -> &Slabs {
+        &self.slabs
+    }
+
+    /// Returns the byte size which each shard charges at once.
+    ///
+    /// See [`set_shard_batch`] for details.
+    ///
+    /// [`set_shard_batch`]: #method.set_shard_batch
+    #[inline]
+    pub fn shard_batch(&self) -> usize {
+        self.batch.load(Ordering::Relaxed)
+    }
+
+    /// Sets the byte size which each shard charges at once.
+    ///
+    /// Each pool has [`SHARDS`] per-CPU credits.
+    /// If `bytes` is greater than 0, each shard charges `bytes` more than required to the pool,
+    /// and the following allocations in the same CPU consume the credit without touching the
+    /// counter shared among CPUs.
+    /// The released memory is kept as the credit up to `2 * bytes` .
+    ///
+    /// The limit is always checked against the size including the credits, so the memory
+    /// actually allocated never exceeds the limit; however, an allocation can fail while
+    /// another shard keeps the credit. In that case, the pool takes all the credits back and
+    /// retries.
+    ///
+    /// [`cache_size`] and the watermarks are based on the size including the credits.
+    /// (Note that the credits are charged to the ancestors as well.)
+    ///
+    /// The default is 0, which disables the shards. Setting 0 takes back all the credits.
+    ///
+    /// [`SHARDS`]: constant.SHARDS.html
+    /// [`cache_size`]: #method.cache_size
+    #[inline]
+    pub fn set_shard_batch(&self, bytes: usize) {
+        self.batch.store(bytes, Ordering::Relaxed);
+
+        if bytes == 0 {
+            let credit = self.shards.drain();
+            if 0 < credit {
+                self.release(credit);
+            }
+        }
+    }
+
+    /// Increase the cache memory size of `self` and all the ancestors by `bytes` , and returns
+    /// the new byte size of `self` .
+    ///
+    /// This method ignores the limits.
+    #[inline]
+    pub fn increase_size(&self, bytes: usize) -> usize {
+        if 0 < self.shard_batch() && self.shards.take(bytes) {
+            self.cache_size()
+        } else {
+            self.add(bytes)
+        }
+    }
+
+    /// Decrease the cache memory size of `self` and all the ancestors by `bytes` , and returns
+    /// the new byte size of `self` .
+    #[inline]
+    pub fn decrease_size(&self, bytes: usize) -> usize {
+        let batch = self.shard_batch();
+        if batch == 0 {
+            return self.release(bytes);
+        }
+
+        let excess = self.shards.put(bytes, batch);
+        if 0 < excess {
+            self.release(excess)
+        } else {
+            self.cache_size()
+        }
+    }
+
+    /// Increase the cache memory size of `self` and all the ancestors by `bytes` unless the new
+    /// size exceeds the limit of any of them.
+    ///
+    /// Returns `true` on success, or `false` without changing anything if any limit would be
+    /// exceeded.
+    #[inline]
+    pub fn try_increase_size(&self, bytes: usize) -> bool {
+        let batch = self.shard_batch();
+        if batch == 0 {
+            return self.reserve(bytes);
+        }
+
+        if self.shards.take(bytes) {
+            return true;
+        }
+
+        if self.reserve(bytes.saturating_add(batch)) {
+            let excess = self.shards.put(batch, batch);
+            if 0 < excess {
+                self.release(excess);
+            }
+            return true;
+        }
+
+        if self.reserve(bytes) {
+            return true;
+        }
+
+        // Take back the credits of the other shards and retry.
+        let credit = self.shards.drain();
+        if credit == 0 {
+            return false;
+        }
+
+        self.release(credit);
+        self.reserve(bytes)
+    }
+
+    /// Increase the shared counter of `self` and all the ancestors by `bytes` , and returns the
+    /// new size of `self` .
+    #[inline]
+    fn add(&self, bytes: usize) -> usize {
+        if let Some(parent) = self.parent {
+            parent.increase_size(bytes);
+        }
+
+        let new_size = self.size.fetch_add(bytes, Ordering::Acquire) + bytes;
+        self.peak.fetch_max(new_size, Ordering::Relaxed);
+        self.check_high_watermark(new_size);
+        new_size
+    }
+
+    /// Decrease the shared counter of `self` and all the ancestors by `bytes` , and returns the
+    /// new size of `self` .
+    #[inline]
+    fn release(&self, bytes: usize) -> usize {
+        let new_size = self.size.fetch_sub(bytes, Ordering::Acquire) - bytes;
+        self.check_low_watermark(new_size);
+
+        if let Some(parent) = self.parent {
+            parent.decrease_size(bytes);
+        }
+
+        new_size
+    }
+
+    /// Increase the shared counter of `self` and all the ancestors by `bytes` unless the new
+    /// size exceeds the limit of any of them.
+    #[inline]
+    fn reserve(&self, bytes: usize) -> bool {
+        let limit = self.limit();
+        let mut current = self.size.load(Ordering::Relaxed);
+
+        loop {
+            let new_size = match current.checked_add(bytes) {
+                Some(s) if s <= limit => s,
+                _ => return false,
+            };
+
+            match self.size.compare_exchange_weak(
+                current,
+                new_size,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(s) => current = s,
+            }
+        }
+
+        if let Some(parent) = self.parent {
+            if !parent.try_increase_size(bytes) {
+                // Roll back without calling the callbacks.
+                self.size.fetch_sub(bytes, Ordering::Release);
+                return false;
+            }
+        }
+
+        self.peak.fetch_max(current + bytes, Ordering::Relaxed);
+        self.check_high_watermark(current + bytes);
+        true
+    }
+
+    /// Calls the high watermark callback if `size` has just crossed the high watermark.
+    #[inline]
+    fn check_high_watermark(&self, size: usize) {
+        if self.cross_high_watermark(size) {
+            // A concurrent decrease could have checked the flag before it was set. Re-checks
+            // only once, and only under the high watermark; otherwise, a size just on the equal
+            // watermarks would flip the flag forever.
+            let current = self.size.load(Ordering::Acquire);
+            if current < self.high_watermark.load(Ordering::Relaxed) {
+                self.cross_low_watermark(current);
+            }
+        }
+    }
+
+    /// Calls the low watermark callback if `size` has just fallen under the low watermark.
+    #[inline]
+    fn check_low_watermark(&self, size: usize) {
+        if self.cross_low_watermark(size) {
+            // A concurrent increase could have checked the flag before it was cleared. (See
+            // `check_high_watermark` .)
+            let current = self.size.load(Ordering::Acquire);
+            if self.low_watermark.load(Ordering::Relaxed) < current {
+                self.cross_high_watermark(current);
+            }
+        }
+    }
+
+    /// Sets the pressure flag and calls the high watermark callback if `size` is not less than
+    /// the high watermark and the flag is not set.
+    ///
+    /// Returns whether the flag is set by this method or not.
+    #[inline]
+    fn cross_high_watermark(&self, size: usize) -> bool {
+        if size < self.high_watermark.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        if self
+            .under_pressure
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        let f = self.on_high.load(Ordering::Acquire);
+        if !f.is_null() {
+            let f: fn(usize) = unsafe { mem::transmute(f) };
+            f(size);
+        }
+        true
+    }
+
+    /// Clears the pressure flag and calls the low watermark callback if `size` is not greater
+    /// than the low watermark and the flag is set.
+    ///
+    /// Returns whether the flag is cleared by this method or not.
+    #[inline]
+    fn cross_low_watermark(&self, size: usize) -> bool {
+        if self.low_watermark.load(Ordering::Relaxed) < size {
+            return false;
+        }
+
+        if self
+            .under_pressure
+            .compare_exchange(true, false, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        let f = self.on_low.load(Ordering::Acquire);
+        if !f.is_null() {
+            let f: fn(usize) = unsafe { mem::transmute(f) };
+            f(size);
+        }
+        true
+    }
+
+    /// Returns the byte size to be released to allocate `bytes` more.
+    #[inline]
+    pub(crate) fn shortage(&self, bytes: usize) -> usize {
+        let mut excess = 0;
+        let mut pool = Some(self);
+
+        while let Some(p) = pool {
+            let e = p
+                .cache_size()
+                .saturating_add(bytes)
+                .saturating_sub(p.limit());
+            excess = excess.max(e);
+            pool = p.parent;
+        }
+
+        if excess == 0 {
+            bytes
+        } else {
+            excess
+        }
+    }
+
+    /// Calls `f` and returns the result.
+    /// If `f` returns null, calls the shrinkers to release `bytes` and retries `f` once.
+    #[inline]
+    pub(crate) fn retry_with_shrink<F>(&self, bytes: usize, mut f: F) -> *mut u8
+    where
+        F: FnMut() -> *mut u8,
+    {
+        let ptr = f();
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        if shrink(self.shortage(bytes)) == 0 {
+            return ptr;
+        }
+
+        f()
+    }
+}
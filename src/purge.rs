@@ -0,0 +1,156 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Purgeable memory of `CMmapAlloc` .
+
+use crate::CachePool;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Value written at the head of each purged page.
+///
+/// The kernel replaces a discarded page with a zero-filled one, so the sentinel tells whether
+/// the page has survived.
+const SENTINEL: usize = usize::MAX / 0xff * 0xa5;
+
+/// Token of the memory which [`CMmapAlloc::purge`] marked purgeable.
+///
+/// The kernel may discard the pages while the token is alive. Call [`reclaim`] to use the memory
+/// again. Dropping the token without `reclaim` leaves the contents of the memory unspecified,
+/// although the memory itself is still valid until deallocated.
+///
+/// [`CMmapAlloc::purge`]: struct.CMmapAlloc.html#method.purge
+/// [`reclaim`]: #method.reclaim
+#[must_use]
+pub struct Purged {
+    ptr: *mut u8,
+    len: usize,
+    pool: &'static CachePool,
+    /// The original words replaced with the sentinel.
+    saved: Vec<usize>,
+}
+
+impl Purged {
+    /// Marks `len` bytes from `ptr` purgeable and returns the token.
+    ///
+    /// If the OS does not support the purge, the memory stays committed; then `reclaim` always
+    /// succeeds.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned to the page size, `len` must be a multiple of the page size, and
+    /// the memory must be mapped and be charged to `pool` .
+    pub(crate) unsafe fn new(ptr: *mut u8, len: usize, pool: &'static CachePool) -> Self {
+        let page_size = mmap_allocator::page_size();
+        let saved = (0..len / page_size)
+            .map(|i| {
+                let word = ptr.add(i * page_size) as *mut usize;
+                word.replace(SENTINEL)
+            })
+            .collect();
+
+        if crate::os::advise_free(ptr, len) {
+            pool.add_purgeable(len);
+            Self {
+                ptr,
+                len,
+                pool,
+                saved,
+            }
+        } else {
+            restore(ptr, &saved);
+            Self {
+                ptr,
+                len: 0,
+                pool,
+                saved: Vec::new(),
+            }
+        }
+    }
+
+    /// Returns the pointer to the purged memory.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the byte size of the purgeable memory. (0 if the OS does not support the purge.)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no byte is purgeable.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Makes the memory committed again, and returns whether the contents have survived.
+    ///
+    /// If `false` is returned, the kernel has discarded some pages and they are filled with 0.
+    /// The caller should recompute the contents.
+    pub fn reclaim(mut self) -> bool {
+        let page_size = mmap_allocator::page_size();
+        let mut survived = true;
+
+        for (i, &word) in self.saved.iter().enumerate() {
+            // Writing to the page cancels `MADV_FREE` for it. `compare_exchange` checks and
+            // writes atomically so that the kernel cannot discard the page between them.
+            // (A discarded page is left filled with 0.)
+            let head = unsafe { &*(self.ptr.add(i * page_size) as *const AtomicUsize) };
+            if head
+                .compare_exchange(SENTINEL, word, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+            {
+                survived = false;
+            }
+        }
+
+        self.pool.sub_purgeable(self.len);
+        self.len = 0;
+        self.saved = Vec::new();
+        survived
+    }
+}
+
+impl Drop for Purged {
+    fn drop(&mut self) {
+        self.pool.sub_purgeable(self.len);
+    }
+}
+
+/// Writes `saved` back to the head of each page from `ptr` .
+unsafe fn restore(ptr: *mut u8, saved: &[usize]) {
+    let page_size = mmap_allocator::page_size();
+    for (i, &word) in saved.iter().enumerate() {
+        (ptr.add(i * page_size) as *mut usize).write(word);
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of the live allocations charged by `Counted` , i.e. `CAlloc` , `CPoolAlloc` , and
+//! `Counted` itself.
+//!
+//! The registry is a global table keyed by the address. It is allocated through the global
+//! allocator; while a thread is updating the registry, the allocations of the thread are not
+//! registered, so that the registry works even if the global allocator is `Counted` .
+//!
+//! Under feature `check` , the registry keeps the addresses recently deallocated as well
+//! (tombstones,) to validate the pointers passed to `dealloc` and `realloc` . It also counts the
+//! allocations which were not registered, so as not to report them as invalid.
+
+use crate::CachePool;
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::fmt;
+#[cfg(feature = "check")]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+#[cfg(feature = "check")]
+use std::collections::VecDeque;
+use std::io;
+#[cfg(feature = "check")]
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Allocation which is registered and not deallocated yet.
+///
+/// This struct is available only if feature `registry` is enabled.
+/// See [`CachePool::live_allocations`] for details.
+///
+/// [`CachePool::live_allocations`]: struct.CachePool.html#method.live_allocations
+#[derive(Debug, Clone)]
+pub struct LiveAllocation {
+    /// The address of the memory.
+    pub address: usize,
+    /// The layout which the memory was allocated with.
+    pub layout: Layout,
+    /// The bytes charged to the pool for the memory.
+    pub charged: usize,
+    /// Where the memory was allocated if [`set_registry_backtrace`] was enabled.
+    ///
+    /// [`set_registry_backtrace`]: fn.set_registry_backtrace.html
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+impl fmt::Display for LiveAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} (size: {}, align: {}, charged: {})",
+            self.address,
+            self.layout.size(),
+            self.layout.align(),
+            self.charged
+        )?;
+
+        match &self.backtrace {
+            Some(backtrace) => write!(f, "\n{}", backtrace),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sets whether the registry captures the backtrace of each allocation. (Default is `false` .)
+///
+/// Capturing the backtrace is very slow.
+///
+/// This function is available only if feature `registry` is enabled.
+#[inline]
+pub fn set_registry_backtrace(enabled: bool) {
+    BACKTRACE.store(enabled, Ordering::Relaxed);
+}
+
+static BACKTRACE: AtomicBool = AtomicBool::new(false);
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// The number of the live allocations which were not registered because the thread was updating
+/// the registry or being destroyed.
+#[cfg(feature = "check")]
+static UNREGISTERED: AtomicUsize = AtomicUsize::new(0);
+
+/// The max number of the tombstones.
+#[cfg(feature = "check")]
+const TOMBSTONES: usize = 4096;
+
+thread_local! {
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Registry {
+    lives: BTreeMap<usize, Entry>,
+    /// The layouts of the memory recently deallocated.
+    #[cfg(feature = "check")]
+    tombstones: BTreeMap<usize, Layout>,
+    /// The addresses of `tombstones` in the order of the deallocation.
+    #[cfg(feature = "check")]
+    order: VecDeque<usize>,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            lives: BTreeMap::new(),
+            #[cfg(feature = "check")]
+            tombstones: BTreeMap::new(),
+            #[cfg(feature = "check")]
+            order: VecDeque::new(),
+        }
+    }
+}
+
+struct Entry {
+    layout: Layout,
+    charged: usize,
+    pool: &'static CachePool,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+/// Calls `f` with the registry unless the current thread is updating the registry.
+fn with_registry<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Registry) -> R,
+{
+    BUSY.try_with(|busy| {
+        if busy.replace(true) {
+            return None;
+        }
+
+        let ret = {
+            let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut registry)
+        };
+
+        busy.set(false);
+        Some(ret)
+    })
+    .ok()
+    .flatten()
+}
+
+/// Registers `ptr` allocated with `layout` , which `charged` bytes are charged to `pool` for.
+#[inline]
+pub fn insert(ptr: *mut u8, layout: Layout, charged: usize, pool: &'static CachePool) {
+    let inserted = with_registry(|registry| {
+        let backtrace = if BACKTRACE.load(Ordering::Relaxed) {
+            Some(Arc::new(Backtrace::force_capture()))
+        } else {
+            None
+        };
+
+        let entry = Entry {
+            layout,
+            charged,
+            pool,
+            backtrace,
+        };
+        registry.lives.insert(ptr as usize, entry);
+
+        #[cfg(feature = "check")]
+        registry.tombstones.remove(&(ptr as usize));
+    });
+
+    #[cfg(feature = "check")]
+    if inserted.is_none() {
+        UNREGISTERED.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(not(feature = "check"))]
+    let _ = inserted;
+}
+
+/// Entry which [`remove`] took out of the registry, and [`restore`] can put back.
+///
+/// [`remove`]: fn.remove.html
+/// [`restore`]: fn.restore.html
+pub struct Removed(Option<Entry>);
+
+/// Unregisters `ptr` , and returns the entry unless the current thread is updating the
+/// registry.
+///
+/// Call this function before the memory is released; otherwise, another thread could allocate
+/// and register the same address in the meantime.
+#[inline]
+pub fn remove(ptr: *mut u8) -> Option<Removed> {
+    with_registry(|registry| {
+        let entry = registry.lives.remove(&(ptr as usize));
+
+        #[cfg(feature = "check")]
+        if let Some(entry) = &entry {
+            if TOMBSTONES <= registry.order.len() {
+                if let Some(address) = registry.order.pop_front() {
+                    registry.tombstones.remove(&address);
+                }
+            }
+            registry.tombstones.insert(ptr as usize, entry.layout);
+            registry.order.push_back(ptr as usize);
+        } else {
+            // `ptr` must be one of the allocations not registered.
+            let _ = UNREGISTERED
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        }
+
+        Removed(entry)
+    })
+}
+
+/// Puts `removed` back as the entry of `ptr` , e.g. because `realloc` failed and `ptr` is still
+/// alive.
+#[inline]
+pub fn restore(ptr: *mut u8, removed: Removed) {
+    let restored = with_registry(|registry| match removed.0 {
+        Some(entry) => {
+            registry.lives.insert(ptr as usize, entry);
+
+            #[cfg(feature = "check")]
+            registry.tombstones.remove(&(ptr as usize));
+            true
+        }
+        None => false,
+    });
+
+    #[cfg(feature = "check")]
+    if restored != Some(true) {
+        UNREGISTERED.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(not(feature = "check"))]
+    let _ = restored;
+}
+
+/// Checks that `ptr` is allocated with `layout` and charged to `pool` , and aborts the process
+/// if not.
+///
+/// `layout` fits the allocation if the alignment is the same and the size is between the
+/// requested size and the usable size.
+///
+/// The pointers not registered are not reported as foreign nor freed twice while any allocation
+/// is not registered, because they can be one of them. Nothing is checked if the current thread
+/// is updating the registry.
+///
+/// `op` is the name of the operation for the diagnostic.
+#[cfg(feature = "check")]
+#[inline]
+pub fn check(op: &str, ptr: *mut u8, layout: Layout, pool: &'static CachePool) {
+    let error = with_registry(|registry| {
+        let address = ptr as usize;
+        match registry.lives.get(&address) {
+            Some(entry) if !core::ptr::eq(entry.pool, pool) => Some(Invalid::Pool),
+            Some(entry) if !fits(entry, layout) => {
+                Some(Invalid::Layout(entry.layout, entry.charged))
+            }
+            Some(_) => None,
+            // `ptr` can be one of the allocations not registered, which may reuse the address
+            // of a tombstone.
+            None if 0 < UNREGISTERED.load(Ordering::Relaxed) => None,
+            None => match registry.tombstones.get(&address) {
+                Some(&layout) => Some(Invalid::DoubleFree(layout)),
+                None => Some(Invalid::Foreign),
+            },
+        }
+    });
+
+    if let Some(Some(error)) = error {
+        // Writes to `stderr` directly as `poison::release` does.
+        let _ = writeln!(
+            io::stderr(),
+            "mouse-cache-alloc: invalid {} of {:#x} (size: {}, align: {}): {}",
+            op,
+            ptr as usize,
+            layout.size(),
+            layout.align(),
+            error
+        );
+        std::process::abort();
+    }
+}
+
+/// Returns whether `layout` fits the allocation of `entry` or not.
+#[cfg(feature = "check")]
+#[inline]
+fn fits(entry: &Entry, layout: Layout) -> bool {
+    let usable = core::cmp::max(entry.layout.size(), entry.charged);
+    entry.layout.align() == layout.align()
+        && entry.layout.size() <= layout.size()
+        && layout.size() <= usable
+}
+
+/// Reason why a pointer passed to `dealloc` or `realloc` is invalid.
+#[cfg(feature = "check")]
+enum Invalid {
+    /// The pointer was not allocated by the allocator.
+    Foreign,
+    /// The pointer was deallocated already.
+    DoubleFree(Layout),
+    /// The pointer was allocated by an allocator charging to another pool.
+    Pool,
+    /// The pointer was allocated with the layout and the usable size which the layout does not
+    /// fit.
+    Layout(Layout, usize),
+}
+
+#[cfg(feature = "check")]
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Foreign => f.write_str("not allocated by this allocator"),
+            Self::DoubleFree(layout) => write!(
+                f,
+                "double free (deallocated with size: {}, align: {})",
+                layout.size(),
+                layout.align()
+            ),
+            Self::Pool => f.write_str("allocated by an allocator of another pool"),
+            Self::Layout(layout, usable) => write!(
+                f,
+                "allocated with size: {}, align: {} (usable: {})",
+                layout.size(),
+                layout.align(),
+                usable
+            ),
+        }
+    }
+}
+
+/// Returns the live allocations charged to the pools which `filter` returns true for.
+pub fn live<F>(filter: F) -> Vec<LiveAllocation>
+where
+    F: Fn(&CachePool) -> bool,
+{
+    let lives = with_registry(|registry| {
+        registry
+            .lives
+            .iter()
+            .filter(|(_, entry)| filter(entry.pool))
+            .map(|(&address, entry)| LiveAllocation {
+                address,
+                layout: entry.layout,
+                charged: entry.charged,
+                backtrace: entry.backtrace.clone(),
+            })
+            .collect()
+    });
+
+    lives.unwrap_or_default()
+}
+
+/// Returns the sum of the charged bytes of the live allocations charged to the pools which
+/// `filter` returns true for.
+pub fn charged<F>(filter: F) -> usize
+where
+    F: Fn(&CachePool) -> bool,
+{
+    let charged = with_registry(|registry| {
+        registry
+            .lives
+            .values()
+            .filter(|entry| filter(entry.pool))
+            .map(|entry| entry.charged)
+            .sum()
+    });
+
+    charged.unwrap_or(0)
+}
+
+/// Writes `lives` and the difference between their charged bytes and `cache_size` into `w` .
+pub fn write_report<W>(mut w: W, lives: &[LiveAllocation], cache_size: usize) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let registered: usize = lives.iter().map(|live| live.charged).sum();
+    writeln!(
+        w,
+        "{} live allocations, {} bytes registered, {} bytes charged",
+        lives.len(),
+        registered,
+        cache_size
+    )?;
+
+    if registered < cache_size {
+        writeln!(
+            w,
+            "{} bytes are charged without allocations",
+            cache_size - registered
+        )?;
+    } else if cache_size < registered {
+        writeln!(
+            w,
+            "{} bytes are registered but not charged",
+            registered - cache_size
+        )?;
+    }
+
+    for live in lives {
+        writeln!(w, "{}", live)?;
+    }
+
+    Ok(())
+}
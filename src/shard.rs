@@ -0,0 +1,152 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-CPU credit of the cache memory size.
+//!
+//! Each shard holds bytes which have already been charged to the pool but not used yet.
+//! Allocations consume the credit of the shard of the current CPU without touching the shared
+//! counter as long as the credit is enough.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of the shards of each pool.
+pub const SHARDS: usize = 64;
+
+/// Credit of a CPU. It is aligned to the cache line to avoid false sharing.
+#[repr(align(64))]
+struct Shard {
+    credit: AtomicUsize,
+}
+
+impl Shard {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        credit: AtomicUsize::new(0),
+    };
+}
+
+/// Set of the per-CPU credits.
+pub struct Shards([Shard; SHARDS]);
+
+impl Shards {
+    /// Creates a new instance with no credit.
+    #[inline]
+    pub const fn new() -> Self {
+        Self([Shard::EMPTY; SHARDS])
+    }
+
+    /// Consumes `bytes` from the credit of the current CPU if it is enough.
+    ///
+    /// Returns `true` on success, or `false` without changing anything.
+    #[inline]
+    pub fn take(&self, bytes: usize) -> bool {
+        let credit = &self.0[index()].credit;
+        let mut current = credit.load(Ordering::Relaxed);
+
+        while bytes <= current {
+            match credit.compare_exchange_weak(
+                current,
+                current - bytes,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(c) => current = c,
+            }
+        }
+
+        false
+    }
+
+    /// Adds `bytes` to the credit of the current CPU.
+    ///
+    /// If the credit exceeds `2 * batch` , the credit is reduced to `batch` and the excess is
+    /// returned. (The caller should return the excess to the pool.)
+    #[inline]
+    pub fn put(&self, bytes: usize, batch: usize) -> usize {
+        let credit = &self.0[index()].credit;
+        let mut current = credit.load(Ordering::Relaxed);
+
+        loop {
+            let total = current + bytes;
+            let (keep, excess) = if total <= 2 * batch {
+                (total, 0)
+            } else {
+                (batch, total - batch)
+            };
+
+            match credit.compare_exchange_weak(current, keep, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return excess,
+                Err(c) => current = c,
+            }
+        }
+    }
+
+    /// Takes all the credits and returns the sum of them.
+    #[inline]
+    pub fn drain(&self) -> usize {
+        self.0
+            .iter()
+            .map(|s| s.credit.swap(0, Ordering::AcqRel))
+            .sum()
+    }
+
+    /// Returns the sum of the credits.
+    #[inline]
+    pub fn sum(&self) -> usize {
+        self.0
+            .iter()
+            .map(|s| s.credit.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+/// Returns the index of the shard of the current CPU.
+#[cfg(all(target_os = "linux", not(miri)))]
+#[inline]
+pub fn index() -> usize {
+    match unsafe { libc::sched_getcpu() } {
+        cpu if cpu < 0 => 0,
+        cpu => cpu as usize % SHARDS,
+    }
+}
+
+/// Returns the index of the shard of the current thread.
+#[cfg(any(not(target_os = "linux"), miri))]
+#[inline]
+pub fn index() -> usize {
+    thread_local! {
+        static KEY: u8 = const { 0 };
+    }
+
+    KEY.with(|k| (k as *const u8 as usize) >> 6) % SHARDS
+}
@@ -0,0 +1,185 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Registry of shrinkers, which are called to release cache memory when an allocation fails.
+//!
+//! This module is modelled on the Linux kernel shrinker API.
+
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicI32, AtomicPtr, AtomicUsize, Ordering};
+
+/// The max number of shrinkers registered at the same time.
+pub const MAX_SHRINKERS: usize = 64;
+
+/// Function to release cache memory.
+///
+/// It is passed the byte size to be released, and returns the byte size actually released.
+/// (The return value can be larger or smaller than the argument.)
+pub type Shrinker = fn(usize) -> usize;
+
+/// Identifier of a registered shrinker, which is used to unregister it.
+///
+/// It consists of the slot index and the generation of the slot, so that a stale id does not
+/// unregister another shrinker reusing the slot.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ShrinkerId {
+    index: usize,
+    generation: usize,
+}
+
+/// Marker of the slot being registered.
+const RESERVED: *mut () = ptr::dangling_mut();
+
+struct Slot {
+    shrinker: AtomicPtr<()>,
+    priority: AtomicI32,
+    // Incremented whenever the shrinker is unregistered.
+    generation: AtomicUsize,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        shrinker: AtomicPtr::new(ptr::null_mut()),
+        priority: AtomicI32::new(0),
+        generation: AtomicUsize::new(0),
+    };
+}
+
+static SLOTS: [Slot; MAX_SHRINKERS] = [Slot::EMPTY; MAX_SHRINKERS];
+
+/// Registers `shrinker` with `priority` and returns its id.
+///
+/// The shrinkers are called in descending order of `priority` .
+/// (The shrinkers with the same priority are called in arbitrary order.)
+///
+/// Returns `None` if [`MAX_SHRINKERS`] shrinkers have already been registered.
+///
+/// `shrinker` is called in the thread failing to allocate memory, in the middle of the
+/// allocation. It should release memory synchronously, and must not allocate cache memory.
+///
+/// [`MAX_SHRINKERS`]: constant.MAX_SHRINKERS.html
+pub fn register_shrinker(priority: i32, shrinker: Shrinker) -> Option<ShrinkerId> {
+    for (i, slot) in SLOTS.iter().enumerate() {
+        if slot
+            .shrinker
+            .compare_exchange(
+                ptr::null_mut(),
+                RESERVED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            slot.priority.store(priority, Ordering::Relaxed);
+            let generation = slot.generation.load(Ordering::Relaxed);
+            slot.shrinker.store(shrinker as *mut (), Ordering::Release);
+            return Some(ShrinkerId {
+                index: i,
+                generation,
+            });
+        }
+    }
+
+    None
+}
+
+/// Unregisters the shrinker registered as `id` .
+///
+/// Returns `false` and does nothing if `id` has already been unregistered. (Even if another
+/// shrinker has been registered to the same slot since then, it is not unregistered.)
+///
+/// Note that the shrinker can be still called by another thread which has started to call the
+/// shrinkers before this function is called.
+pub fn unregister_shrinker(id: ShrinkerId) -> bool {
+    let slot = &SLOTS[id.index];
+
+    // Only one of the concurrent calls with the same id can win.
+    if slot
+        .generation
+        .compare_exchange(
+            id.generation,
+            id.generation.wrapping_add(1),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .is_err()
+    {
+        return false;
+    }
+
+    slot.shrinker.store(ptr::null_mut(), Ordering::Release);
+    true
+}
+
+/// Calls the registered shrinkers in descending order of the priority until `target` bytes
+/// are released, and returns the byte size released in total.
+///
+/// The allocators of this crate call this function when an allocation fails.
+pub fn shrink(target: usize) -> usize {
+    if target == 0 {
+        return 0;
+    }
+
+    // Collect the registered shrinkers and sort them by insertion sort.
+    // (Memory must not be allocated here.)
+    let mut shrinkers: [(i32, *mut ()); MAX_SHRINKERS] = [(0, ptr::null_mut()); MAX_SHRINKERS];
+    let mut len = 0;
+
+    for slot in SLOTS.iter() {
+        let f = slot.shrinker.load(Ordering::Acquire);
+        if f.is_null() || f == RESERVED {
+            continue;
+        }
+
+        let priority = slot.priority.load(Ordering::Relaxed);
+        let mut i = len;
+        while 0 < i && shrinkers[i - 1].0 < priority {
+            shrinkers[i] = shrinkers[i - 1];
+            i -= 1;
+        }
+        shrinkers[i] = (priority, f);
+        len += 1;
+    }
+
+    let mut released: usize = 0;
+    for &(_, f) in shrinkers[..len].iter() {
+        let f: Shrinker = unsafe { mem::transmute(f) };
+        released = released.saturating_add(f(target - released));
+
+        if target <= released {
+            break;
+        }
+    }
+
+    released
+}
@@ -0,0 +1,292 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slab layer of `CMmapAlloc` , which carves OS pages into fixed-size slots.
+//!
+//! Each slab is an OS page starting with a [`Slab`] header followed by the slots of a size
+//! class. The slabs with free slots are linked in the list of the class, and the full slabs are
+//! unlinked until a slot is freed.
+//!
+//! The slabs of different [`Attrs`] never share a page; e.g. the objects of a `CMmapAlloc`
+//! locking the memory are never placed on the pages which are not locked.
+
+use core::alloc::Layout;
+use core::{cmp, mem, ptr};
+use std::sync::{Mutex, MutexGuard};
+
+/// The number of the size classes. (16, 32, ... , 1024 bytes.)
+pub const CLASSES: usize = 7;
+
+/// The smallest size class.
+const MIN_CLASS: usize = 16;
+
+/// Returns the byte size of the slots of `class` .
+#[inline]
+pub const fn class_size(class: usize) -> usize {
+    MIN_CLASS << class
+}
+
+/// Returns the size class to allocate `layout` , or `None` if `layout` is too large for the
+/// slabs.
+///
+/// At least 4 slots are required per slab; otherwise, mapping a page for each allocation is
+/// better.
+#[inline]
+pub fn class_of(layout: Layout) -> Option<usize> {
+    let size = cmp::max(cmp::max(layout.size(), layout.align()), MIN_CLASS);
+    let size = size.checked_next_power_of_two()?;
+    let class = (size / MIN_CLASS).trailing_zeros() as usize;
+
+    if class < CLASSES && size <= page_size() / 4 {
+        Some(class)
+    } else {
+        None
+    }
+}
+
+/// Returns the OS page size, which is the size of each slab.
+#[inline]
+pub fn page_size() -> usize {
+    mmap_allocator::page_size()
+}
+
+/// Returns the layout of each slab.
+#[inline]
+pub fn page_layout() -> Layout {
+    let page_size = page_size();
+    unsafe { Layout::from_size_align_unchecked(page_size, page_size) }
+}
+
+/// Attributes of the pages of a slab, which `CMmapAlloc` applies when mapping it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attrs {
+    /// Whether the page is locked in RAM. (`mlock` .)
+    pub locked: bool,
+    /// Whether the page is excluded from core dumps. (`MADV_DONTDUMP` .)
+    pub dont_dump: bool,
+}
+
+/// The number of the combinations of [`Attrs`] .
+const ATTRS: usize = 4;
+
+impl Attrs {
+    /// Returns the index of the slab lists of `self` .
+    #[inline]
+    fn index(self) -> usize {
+        self.locked as usize | (self.dont_dump as usize) << 1
+    }
+}
+
+/// Header of each slab.
+#[repr(C)]
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: *mut Slot,
+    used: usize,
+    attrs: Attrs,
+}
+
+/// Free slot, which is linked to the next free slot in the same slab.
+struct Slot {
+    next: *mut Slot,
+}
+
+/// Doubly linked list of the slabs with free slots.
+struct List {
+    head: *mut Slab,
+}
+
+// The slabs are accessed only while the list is locked.
+unsafe impl Send for List {}
+
+impl List {
+    #[inline]
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = slab;
+        }
+        self.head = slab;
+    }
+
+    #[inline]
+    unsafe fn unlink(&mut self, slab: *mut Slab) {
+        let prev = (*slab).prev;
+        let next = (*slab).next;
+
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
+
+/// Set of the slab lists of a pool for each [`Attrs`] and size class.
+pub struct Slabs([[Mutex<List>; CLASSES]; ATTRS]);
+
+impl Slabs {
+    /// Creates a new instance without any slab.
+    #[inline]
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: Mutex<List> = Mutex::new(List {
+            head: ptr::null_mut(),
+        });
+        #[allow(clippy::declare_interior_mutable_const)]
+        const CLASSES_EMPTY: [Mutex<List>; CLASSES] = [EMPTY; CLASSES];
+        Self([CLASSES_EMPTY; ATTRS])
+    }
+
+    #[inline]
+    fn lock(&self, attrs: Attrs, class: usize) -> MutexGuard<'_, List> {
+        self.0[attrs.index()][class]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes a slot of `class` from the slabs of `attrs` .
+    ///
+    /// If no slab has a free slot, calls `new_page` to get a page for a new slab. The page
+    /// must have `attrs` .
+    /// Returns null if `new_page` returns null.
+    ///
+    /// `new_page` is called without the lock, because charging the pool can call the pressure
+    /// callbacks, which may deallocate to the same class.
+    ///
+    /// # Safety
+    ///
+    /// `new_page` must return a page aligned to the page size, or null.
+    pub unsafe fn alloc<F>(&self, attrs: Attrs, class: usize, new_page: F) -> *mut u8
+    where
+        F: FnOnce() -> *mut u8,
+    {
+        {
+            let mut list = self.lock(attrs, class);
+            if !list.head.is_null() {
+                return take(&mut list);
+            }
+        }
+
+        let page = new_page();
+        if page.is_null() {
+            return ptr::null_mut();
+        }
+
+        // Another thread may have added a slab meanwhile; then, the new one is just kept for later.
+        let mut list = self.lock(attrs, class);
+        list.push(init(page, attrs, class));
+        take(&mut list)
+    }
+
+    /// Returns `ptr` to the slab of `class` .
+    ///
+    /// If the slab gets empty, unlinks and returns the page of the slab and its attributes.
+    /// The caller should release it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be what `alloc` returned with `class` , and must not have been deallocated
+    /// yet.
+    pub unsafe fn dealloc(&self, class: usize, ptr: *mut u8) -> Option<(*mut u8, Attrs)> {
+        let page = (ptr as usize & !(page_size() - 1)) as *mut u8;
+        let slab = page as *mut Slab;
+        let slot = ptr as *mut Slot;
+
+        // The attributes are never changed after the slab is built.
+        let attrs = (*slab).attrs;
+        let mut list = self.lock(attrs, class);
+        let was_full = (*slab).free.is_null();
+
+        (*slot).next = (*slab).free;
+        (*slab).free = slot;
+        (*slab).used -= 1;
+
+        if (*slab).used == 0 {
+            if !was_full {
+                list.unlink(slab);
+            }
+            Some((page, attrs))
+        } else {
+            if was_full {
+                list.push(slab);
+            }
+            None
+        }
+    }
+}
+
+/// Takes a free slot from the head of `list` , which must not be empty.
+#[inline]
+unsafe fn take(list: &mut List) -> *mut u8 {
+    let slab = list.head;
+    let slot = (*slab).free;
+    (*slab).free = (*slot).next;
+    (*slab).used += 1;
+
+    if (*slab).free.is_null() {
+        list.unlink(slab);
+    }
+
+    slot as *mut u8
+}
+
+/// Builds a slab of `class` with `attrs` on `page` and returns it.
+unsafe fn init(page: *mut u8, attrs: Attrs, class: usize) -> *mut Slab {
+    let size = class_size(class);
+    let first = mem::size_of::<Slab>().div_ceil(size) * size;
+
+    let slab = page as *mut Slab;
+    slab.write(Slab {
+        prev: ptr::null_mut(),
+        next: ptr::null_mut(),
+        free: ptr::null_mut(),
+        used: 0,
+        attrs,
+    });
+
+    // Links the slots so that the lower address is taken first.
+    let mut offset = page_size() - size;
+    while first <= offset {
+        let slot = page.add(offset) as *mut Slot;
+        (*slot).next = (*slab).free;
+        (*slab).free = slot;
+        offset -= size;
+    }
+
+    slab
+}
@@ -0,0 +1,260 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Allocation statistics of the pools.
+
+use crate::shard::{index, SHARDS};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Snapshot of the allocation statistics of a pool.
+///
+/// The counters are maintained per CPU and summed up when the snapshot is taken, so the
+/// snapshot is not atomic as a whole while other threads are allocating memory.
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of the successful allocations. (`alloc` and `alloc_zeroed` .)
+    pub allocations: usize,
+    /// The number of the deallocations.
+    pub deallocations: usize,
+    /// The number of the successful reallocations.
+    pub reallocations: usize,
+    /// The number of the failed allocations and reallocations.
+    pub failures: usize,
+    /// The number of the allocations not deallocated yet.
+    pub live_allocations: usize,
+    /// The sum of `Layout::size()` of the live allocations.
+    pub requested_bytes: usize,
+    /// The bytes charged to the pool, i.e. `CachePool::cache_size_exact()` .
+    ///
+    /// This can be larger than `requested_bytes` because the allocators charge the size
+    /// actually allocated. (e.g. `allocating_size` for `CAlloc` and the page-rounded size for
+    /// `CMmapAlloc` .)
+    pub charged_bytes: usize,
+    /// The max of `CachePool::cache_size()` ever.
+    pub peak_bytes: usize,
+}
+
+/// The number of the buckets of [`SizeHistogram`] .
+///
+/// [`SizeHistogram`]: struct.SizeHistogram.html
+#[cfg(feature = "histogram")]
+pub const HISTOGRAM_BUCKETS: usize = usize::BITS as usize + 1;
+
+/// Snapshot of the histogram of the live allocation sizes. (`Layout::size()` .)
+///
+/// The `i` th bucket counts the live allocations whose size `s` satisfies
+/// `2.pow(i - 1) <= s < 2.pow(i)` . (The 0th bucket counts the allocations of size 0.)
+///
+/// This struct is available only if feature `histogram` is enabled.
+///
+/// ```
+/// use mouse_cache_alloc::{CachePool, CPoolAlloc};
+/// use std::alloc::{GlobalAlloc, Layout};
+///
+/// static POOL: CachePool = CachePool::new();
+///
+/// let alloc = CPoolAlloc::new(&POOL);
+/// let layout = Layout::new::<[u8; 100]>();
+/// let ptr = unsafe { alloc.alloc(layout) };
+///
+/// // 64 <= 100 < 128
+/// let histogram = POOL.histogram();
+/// assert_eq!(1, histogram.buckets()[7]);
+/// assert_eq!(Some((64, 1)), histogram.iter().find(|&(_, count)| 0 < count));
+///
+/// unsafe { alloc.dealloc(ptr, layout) };
+/// ```
+#[cfg(feature = "histogram")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeHistogram([usize; HISTOGRAM_BUCKETS]);
+
+#[cfg(feature = "histogram")]
+impl SizeHistogram {
+    /// Returns the counts of all the buckets.
+    #[inline]
+    pub fn buckets(&self) -> &[usize] {
+        &self.0
+    }
+
+    /// Returns an iterator of the lower bound of the size and the count of each bucket.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (lower_bound(i), count))
+    }
+
+    /// Returns the index of the bucket of `size` .
+    #[inline]
+    pub fn bucket_index(size: usize) -> usize {
+        (usize::BITS - size.leading_zeros()) as usize
+    }
+}
+
+/// Returns the min size of the `i` th bucket.
+#[cfg(feature = "histogram")]
+#[inline]
+fn lower_bound(i: usize) -> usize {
+    if i == 0 {
+        0
+    } else {
+        1 << (i - 1)
+    }
+}
+
+/// Counters of a CPU. It is aligned to the cache line to avoid false sharing.
+#[repr(align(64))]
+struct Slot {
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    reallocations: AtomicUsize,
+    failures: AtomicUsize,
+    // Wrapping sum of the requested sizes. Only the sum of all the slots is meaningful.
+    requested: AtomicUsize,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        allocations: AtomicUsize::new(0),
+        deallocations: AtomicUsize::new(0),
+        reallocations: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+        requested: AtomicUsize::new(0),
+    };
+}
+
+/// Per-CPU allocation counters.
+pub struct Counters {
+    slots: [Slot; SHARDS],
+    #[cfg(feature = "histogram")]
+    histogram: [AtomicUsize; HISTOGRAM_BUCKETS],
+}
+
+impl Counters {
+    /// Creates a new instance with all the counters 0.
+    #[inline]
+    pub const fn new() -> Self {
+        #[cfg(feature = "histogram")]
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+        Self {
+            slots: [Slot::EMPTY; SHARDS],
+            #[cfg(feature = "histogram")]
+            histogram: [ZERO; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    /// Records an allocation of `requested` bytes.
+    #[inline]
+    pub fn alloc(&self, requested: usize) {
+        let slot = &self.slots[index()];
+        slot.allocations.fetch_add(1, Ordering::Relaxed);
+        slot.requested.fetch_add(requested, Ordering::Relaxed);
+
+        #[cfg(feature = "histogram")]
+        self.histogram_add(requested);
+    }
+
+    /// Records a deallocation of `requested` bytes.
+    #[inline]
+    pub fn dealloc(&self, requested: usize) {
+        let slot = &self.slots[index()];
+        slot.deallocations.fetch_add(1, Ordering::Relaxed);
+        slot.requested.fetch_sub(requested, Ordering::Relaxed);
+
+        #[cfg(feature = "histogram")]
+        self.histogram_sub(requested);
+    }
+
+    /// Records a reallocation from `old` bytes to `new` bytes.
+    #[inline]
+    pub fn realloc(&self, old: usize, new: usize) {
+        let slot = &self.slots[index()];
+        slot.reallocations.fetch_add(1, Ordering::Relaxed);
+        slot.requested
+            .fetch_add(new.wrapping_sub(old), Ordering::Relaxed);
+
+        #[cfg(feature = "histogram")]
+        if SizeHistogram::bucket_index(old) != SizeHistogram::bucket_index(new) {
+            self.histogram_sub(old);
+            self.histogram_add(new);
+        }
+    }
+
+    /// Records a failed allocation or reallocation.
+    #[inline]
+    pub fn failure(&self) {
+        self.slots[index()].failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the sums of the counters.
+    /// (`charged_bytes` and `peak_bytes` are left 0.)
+    pub fn sum(&self) -> CacheStats {
+        let mut ret = CacheStats::default();
+        for slot in self.slots.iter() {
+            ret.allocations += slot.allocations.load(Ordering::Relaxed);
+            ret.deallocations += slot.deallocations.load(Ordering::Relaxed);
+            ret.reallocations += slot.reallocations.load(Ordering::Relaxed);
+            ret.failures += slot.failures.load(Ordering::Relaxed);
+            ret.requested_bytes = ret
+                .requested_bytes
+                .wrapping_add(slot.requested.load(Ordering::Relaxed));
+        }
+
+        ret.live_allocations = ret.allocations.saturating_sub(ret.deallocations);
+        ret
+    }
+
+    /// Returns the snapshot of the histogram.
+    #[cfg(feature = "histogram")]
+    pub fn histogram(&self) -> SizeHistogram {
+        let mut ret = [0; HISTOGRAM_BUCKETS];
+        for (r, h) in ret.iter_mut().zip(self.histogram.iter()) {
+            *r = h.load(Ordering::Relaxed);
+        }
+        SizeHistogram(ret)
+    }
+
+    #[cfg(feature = "histogram")]
+    #[inline]
+    fn histogram_add(&self, size: usize) {
+        self.histogram[SizeHistogram::bucket_index(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "histogram")]
+    #[inline]
+    fn histogram_sub(&self, size: usize) {
+        self.histogram[SizeHistogram::bucket_index(size)].fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,441 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Model-based tests checking the cache size against the live allocations.
+
+use mouse_cache_alloc::{
+    CHeaderAlloc, CMmapAlloc, CPoolAlloc, CachePool, Counted, HugePages, HUGE_PAGE_SIZE,
+};
+use rand::Rng;
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::HashSet;
+
+const ITERATIONS: usize = 10_000;
+const MAX_SIZE: usize = 64 * 1024;
+const TOO_LARGE: usize = isize::MAX as usize / 2;
+
+/// Runs random `alloc` , `alloc_zeroed` , `realloc` , and `dealloc` through `alloc` , and checks
+/// that `pool.cache_size_exact()` always equals to the sum of `charged` over the live allocations,
+/// and that `pool.stats()` agrees with the live allocations.
+fn run<A, F>(alloc: &A, pool: &CachePool, charged: F)
+where
+    A: GlobalAlloc,
+    F: Fn(*mut u8, Layout) -> usize,
+{
+    run_with(alloc, pool, MAX_SIZE, |lives| {
+        lives.iter().map(|&(p, l)| charged(p, l)).sum()
+    });
+}
+
+/// Same to `run` except for that the allocation sizes are less than `max_size` , and that
+/// `charged` returns the bytes charged for all the live allocations.
+fn run_with<A, F>(alloc: &A, pool: &CachePool, max_size: usize, charged: F)
+where
+    A: GlobalAlloc,
+    F: Fn(&[(*mut u8, Layout)]) -> usize,
+{
+    let mut rng = rand::thread_rng();
+    let mut lives: Vec<(*mut u8, Layout)> = Vec::new();
+
+    for _ in 0..ITERATIONS {
+        let before = pool.cache_size_exact();
+
+        match rng.gen_range(0, 4) {
+            0 | 1 => {
+                let layout = Layout::from_size_align(rng.gen_range(1, max_size), 8).unwrap();
+                let ptr = if rng.gen() {
+                    unsafe { alloc.alloc(layout) }
+                } else {
+                    unsafe { alloc.alloc_zeroed(layout) }
+                };
+
+                if ptr.is_null() {
+                    assert_eq!(before, pool.cache_size_exact());
+                } else {
+                    lives.push((ptr, layout));
+                }
+            }
+            2 if !lives.is_empty() => {
+                let i = rng.gen_range(0, lives.len());
+                let (ptr, layout) = lives[i];
+
+                // Grows or shrinks slightly to make in-place reallocation likely.
+                let new_size = match rng.gen_range(0, 3) {
+                    0 => (layout.size() + rng.gen_range(1, 64)).min(max_size),
+                    1 => (layout.size() - layout.size() / 8).max(1),
+                    _ => rng.gen_range(1, max_size),
+                };
+
+                let ptr_ = unsafe { alloc.realloc(ptr, layout, new_size) };
+                if ptr_.is_null() {
+                    assert_eq!(before, pool.cache_size_exact());
+                } else {
+                    let layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                    lives[i] = (ptr_, layout);
+                }
+            }
+            _ if !lives.is_empty() => {
+                let i = rng.gen_range(0, lives.len());
+                let (ptr, layout) = lives.swap_remove(i);
+                unsafe { alloc.dealloc(ptr, layout) };
+            }
+            _ => continue,
+        }
+
+        let expected = charged(&lives);
+        assert_eq!(expected, pool.cache_size_exact());
+
+        let stats = pool.stats();
+        let requested: usize = lives.iter().map(|&(_, l)| l.size()).sum();
+        assert_eq!(lives.len(), stats.live_allocations);
+        assert_eq!(requested, stats.requested_bytes);
+        assert_eq!(expected, stats.charged_bytes);
+        assert!(expected <= stats.peak_bytes);
+
+        #[cfg(feature = "histogram")]
+        {
+            let histogram = pool.histogram();
+            assert_eq!(lives.len(), histogram.buckets().iter().sum::<usize>());
+        }
+    }
+
+    for (ptr, layout) in lives {
+        unsafe { alloc.dealloc(ptr, layout) };
+    }
+    assert_eq!(0, pool.cache_size_exact());
+
+    pool.set_shard_batch(0);
+    assert_eq!(0, pool.cache_size());
+}
+
+#[cfg(not(any(feature = "layout_size", feature = "dont_dump")))]
+fn heap_size(ptr: *mut u8, _layout: Layout) -> usize {
+    unsafe { mouse_cache_alloc::allocating_size(ptr) }
+}
+
+#[cfg(all(not(feature = "layout_size"), feature = "dont_dump"))]
+fn heap_size(ptr: *mut u8, layout: Layout) -> usize {
+    use mouse_cache_alloc::{CAlloc, UsableSize};
+    unsafe { CAlloc.usable_size(ptr, layout) }
+}
+
+#[cfg(feature = "layout_size")]
+fn heap_size(_ptr: *mut u8, layout: Layout) -> usize {
+    let granule = mouse_cache_alloc::SIZE_GRANULE;
+    layout.size().div_ceil(granule) * granule
+}
+
+fn page_round(bytes: usize) -> usize {
+    let page_size = mmap_allocator::page_size();
+    bytes.div_ceil(page_size) * page_size
+}
+
+#[test]
+fn calloc() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+
+    run(&alloc, &POOL, heap_size);
+}
+
+#[test]
+fn calloc_limited() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    let alloc = CPoolAlloc::new(&POOL);
+
+    run(&alloc, &POOL, heap_size);
+}
+
+#[test]
+fn calloc_sharded() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_shard_batch(MAX_SIZE);
+    let alloc = CPoolAlloc::new(&POOL);
+
+    run(&alloc, &POOL, heap_size);
+}
+
+#[test]
+fn calloc_sharded_limited() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    POOL.set_shard_batch(MAX_SIZE);
+    let alloc = CPoolAlloc::new(&POOL);
+
+    run(&alloc, &POOL, heap_size);
+}
+
+#[test]
+fn cmmap_alloc() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CMmapAlloc::with_pool(&POOL);
+
+    run(&alloc, &POOL, |_, layout| page_round(layout.size()));
+}
+
+#[test]
+fn cmmap_alloc_limited() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    let alloc = CMmapAlloc::with_pool(&POOL);
+
+    run(&alloc, &POOL, |_, layout| page_round(layout.size()));
+}
+
+#[test]
+fn cmmap_alloc_mlock() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    let alloc = CMmapAlloc::with_pool(&POOL).mlock(true);
+
+    run(&alloc, &POOL, |_, layout| page_round(layout.size()));
+    assert_eq!(0, POOL.locked_size());
+    assert_eq!(None, POOL.take_lock_error());
+}
+
+#[cfg(feature = "guard_pages")]
+#[test]
+fn cmmap_alloc_guard_pages() {
+    use mouse_cache_alloc::GuardPages;
+
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+
+    for placement in [GuardPages::After, GuardPages::Before] {
+        let alloc = CMmapAlloc::with_pool(&POOL)
+            .slab(true)
+            .guard_pages(placement);
+
+        run(&alloc, &POOL, |_, layout| page_round(layout.size()));
+        assert_eq!(0, POOL.guard_size());
+    }
+}
+
+#[test]
+fn cmmap_alloc_slab() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CMmapAlloc::with_pool(&POOL).slab(true);
+
+    run_with(&alloc, &POOL, 4096, slab_charged);
+}
+
+#[test]
+fn cmmap_alloc_slab_limited() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(4096 * 16);
+    let alloc = CMmapAlloc::with_pool(&POOL).slab(true);
+
+    run_with(&alloc, &POOL, 4096, slab_charged);
+}
+
+#[test]
+fn cmmap_alloc_huge_pages() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(HUGE_PAGE_SIZE * 16);
+    let alloc = CMmapAlloc::with_pool(&POOL).huge_pages(HugePages::Enabled);
+
+    run_with(&alloc, &POOL, HUGE_PAGE_SIZE * 4, |lives| {
+        lives
+            .iter()
+            .map(|&(ptr, layout)| {
+                if layout.size() < HUGE_PAGE_SIZE {
+                    page_round(layout.size())
+                } else {
+                    assert_eq!(0, ptr as usize % HUGE_PAGE_SIZE);
+                    layout.size().div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE
+                }
+            })
+            .sum()
+    });
+}
+
+/// Returns the bytes charged by `CMmapAlloc` with the slabs; i.e. the number of the slab pages
+/// and the page-rounded size of the others.
+fn slab_charged(lives: &[(*mut u8, Layout)]) -> usize {
+    let page_size = mmap_allocator::page_size();
+    let is_slab = |layout: &Layout| layout.size() <= 1024.min(page_size / 4);
+
+    let pages: HashSet<usize> = lives
+        .iter()
+        .filter(|(_, l)| is_slab(l))
+        .map(|&(p, _)| p as usize / page_size)
+        .collect();
+    let others: usize = lives
+        .iter()
+        .filter(|(_, l)| !is_slab(l))
+        .map(|(_, l)| page_round(l.size()))
+        .sum();
+
+    pages.len() * page_size + others
+}
+
+#[test]
+fn cheader_alloc() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CHeaderAlloc::with_pool(&POOL);
+
+    run(&alloc, &POOL, |ptr, layout| unsafe {
+        CHeaderAlloc::charged_size(ptr, layout)
+    });
+}
+
+#[test]
+fn cheader_alloc_limited() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    let alloc = CHeaderAlloc::with_pool(&POOL);
+
+    run(&alloc, &POOL, |ptr, layout| unsafe {
+        CHeaderAlloc::charged_size(ptr, layout)
+    });
+}
+
+#[test]
+fn counted_mmap() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(MAX_SIZE * 16);
+    let alloc = Counted::new(mmap_allocator::MmapAllocator, &POOL);
+
+    run(&alloc, &POOL, |_, layout| page_round(layout.size()));
+}
+
+#[cfg(feature = "registry")]
+#[test]
+fn registry() {
+    static PARENT: CachePool = CachePool::new();
+    static POOL: CachePool = CachePool::with_parent(&PARENT);
+    let alloc = CPoolAlloc::new(&POOL);
+
+    run_with(&alloc, &POOL, MAX_SIZE, |lives| {
+        let mut registered: Vec<_> = POOL
+            .live_allocations()
+            .iter()
+            .map(|live| (live.address, live.layout))
+            .collect();
+        let mut expected: Vec<_> = lives.iter().map(|&(p, l)| (p as usize, l)).collect();
+        registered.sort_by_key(|&(a, _)| a);
+        expected.sort_by_key(|&(a, _)| a);
+        assert_eq!(expected, registered);
+
+        // The parent sees the allocations of the child.
+        assert_eq!(POOL.registered_size(), PARENT.registered_size());
+        POOL.registered_size()
+    });
+
+    let mut report = Vec::new();
+    PARENT.write_leak_report(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("0 live allocations, 0 bytes registered, 0 bytes charged\n"));
+}
+
+#[cfg(feature = "registry")]
+#[test]
+fn registry_realloc_concurrent() {
+    static POOL: CachePool = CachePool::new();
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(48, 8).unwrap();
+
+    // The address released by `realloc` of a thread can be allocated by another thread at once.
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            std::thread::spawn(move || unsafe {
+                let alloc = CPoolAlloc::new(&POOL);
+                for _ in 0..ITERATIONS {
+                    let ptr = alloc.alloc(small);
+                    assert!(!ptr.is_null());
+                    let ptr = alloc.realloc(ptr, small, large.size());
+                    assert!(!ptr.is_null());
+                    alloc.dealloc(ptr, large);
+                }
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    assert_eq!(0, POOL.live_allocations().len());
+    assert_eq!(0, POOL.registered_size());
+    assert_eq!(0, POOL.cache_size_exact());
+}
+
+#[cfg(feature = "registry")]
+#[test]
+fn registry_realloc_failure() {
+    static POOL: CachePool = CachePool::new();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let alloc = CPoolAlloc::new(&POOL);
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(alloc.realloc(ptr, layout, TOO_LARGE).is_null());
+
+        // `ptr` is still registered.
+        let lives = POOL.live_allocations();
+        assert_eq!(1, lives.len());
+        assert_eq!((ptr as usize, layout), (lives[0].address, lives[0].layout));
+        assert_eq!(POOL.cache_size_exact(), POOL.registered_size());
+
+        alloc.dealloc(ptr, layout);
+    }
+
+    assert_eq!(0, POOL.registered_size());
+}
+
+#[test]
+fn realloc_failure() {
+    static POOL: CachePool = CachePool::new();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+
+    unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout);
+        let before = POOL.cache_size();
+        assert!(alloc.realloc(ptr, layout, TOO_LARGE).is_null());
+        assert_eq!(before, POOL.cache_size());
+        alloc.dealloc(ptr, layout);
+
+        let alloc = CMmapAlloc::with_pool(&POOL);
+        let ptr = alloc.alloc(layout);
+        let before = POOL.cache_size();
+        assert!(alloc.realloc(ptr, layout, TOO_LARGE).is_null());
+        assert_eq!(before, POOL.cache_size());
+        alloc.dealloc(ptr, layout);
+
+        let alloc = CHeaderAlloc::with_pool(&POOL);
+        let ptr = alloc.alloc(layout);
+        let before = POOL.cache_size();
+        assert!(alloc.realloc(ptr, layout, TOO_LARGE).is_null());
+        assert_eq!(before, POOL.cache_size());
+        alloc.dealloc(ptr, layout);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
@@ -0,0 +1,187 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the implementation of `core::alloc::Allocator` .
+
+#![cfg(feature = "allocator_api")]
+#![feature(allocator_api)]
+
+use mouse_cache_alloc::{CMmapAlloc, CPoolAlloc, CachePool};
+use std::alloc::{Allocator, Layout};
+use std::ptr::NonNull;
+
+/// Returns the bytes of `slice` .
+unsafe fn bytes<'a>(slice: NonNull<[u8]>) -> &'a [u8] {
+    &*slice.as_ptr()
+}
+
+/// Fills all the bytes of `slice` with `byte` .
+unsafe fn fill(slice: NonNull<[u8]>, byte: u8) {
+    (slice.as_ptr() as *mut u8).write_bytes(byte, slice.len());
+}
+
+#[test]
+fn allocate() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+
+    unsafe {
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let slice = alloc.allocate(layout).unwrap();
+
+        // The slack is charged as well.
+        assert!(layout.size() <= slice.len());
+        assert_eq!(slice.len(), POOL.cache_size());
+        fill(slice, 0xff);
+
+        alloc.deallocate(slice.cast(), layout);
+        assert_eq!(0, POOL.cache_size());
+
+        // Zero-sized allocation is not charged.
+        let layout = Layout::from_size_align(0, 8).unwrap();
+        let slice = alloc.allocate(layout).unwrap();
+        assert_eq!(0, slice.len());
+        assert_eq!(0, POOL.cache_size());
+        alloc.deallocate(slice.cast(), layout);
+    }
+}
+
+#[test]
+fn allocate_zeroed() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CMmapAlloc::with_pool(&POOL).slab(true);
+    let layout = Layout::from_size_align(17, 1).unwrap();
+
+    unsafe {
+        // Keeps the slab alive.
+        let keep = alloc.allocate(layout).unwrap();
+
+        let dirty = alloc.allocate(layout).unwrap();
+        assert_eq!(32, dirty.len());
+        fill(dirty, 0xff);
+        alloc.deallocate(dirty.cast(), layout);
+
+        // The slot is reused, and the slack is zeroed as well.
+        let slice = alloc.allocate_zeroed(layout).unwrap();
+        assert_eq!(dirty.cast::<u8>(), slice.cast::<u8>());
+        assert_eq!(32, slice.len());
+        assert!(bytes(slice).iter().all(|&b| b == 0));
+
+        alloc.deallocate(slice.cast(), layout);
+        alloc.deallocate(keep.cast(), layout);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn grow() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+    let old_layout = Layout::from_size_align(100, 8).unwrap();
+    let new_layout = Layout::from_size_align(1000, 8).unwrap();
+
+    unsafe {
+        let slice = alloc.allocate(old_layout).unwrap();
+        fill(slice, 0xa5);
+
+        let slice = alloc.grow(slice.cast(), old_layout, new_layout).unwrap();
+        assert!(new_layout.size() <= slice.len());
+        assert_eq!(slice.len(), POOL.cache_size());
+        assert!(bytes(slice)[..old_layout.size()].iter().all(|&b| b == 0xa5));
+
+        // Changing the alignment.
+        let aligned = Layout::from_size_align(2000, 256).unwrap();
+        let slice = alloc.grow(slice.cast(), new_layout, aligned).unwrap();
+        assert_eq!(0, slice.cast::<u8>().as_ptr() as usize % 256);
+        assert_eq!(slice.len(), POOL.cache_size());
+        assert!(bytes(slice)[..old_layout.size()].iter().all(|&b| b == 0xa5));
+
+        alloc.deallocate(slice.cast(), aligned);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn grow_zeroed() {
+    static POOL: CachePool = CachePool::new();
+    let old_layout = Layout::from_size_align(17, 1).unwrap();
+    let new_layout = Layout::from_size_align(100, 1).unwrap();
+
+    for alloc in [
+        CMmapAlloc::with_pool(&POOL),
+        CMmapAlloc::with_pool(&POOL).slab(true),
+    ] {
+        unsafe {
+            let slice = alloc.allocate(old_layout).unwrap();
+            fill(slice, 0xa5);
+
+            // The bytes from `old_layout.size()` to the end are zeroed, including the slack of
+            // both the old and the new memory.
+            let slice = alloc
+                .grow_zeroed(slice.cast(), old_layout, new_layout)
+                .unwrap();
+            assert!(new_layout.size() <= slice.len());
+            let (old, new) = bytes(slice).split_at(old_layout.size());
+            assert!(old.iter().all(|&b| b == 0xa5));
+            assert!(new.iter().all(|&b| b == 0));
+
+            alloc.deallocate(slice.cast(), new_layout);
+        }
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn shrink() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+    let old_layout = Layout::from_size_align(1000, 8).unwrap();
+    let new_layout = Layout::from_size_align(100, 8).unwrap();
+
+    unsafe {
+        let slice = alloc.allocate(old_layout).unwrap();
+        fill(slice, 0xa5);
+
+        let slice = alloc.shrink(slice.cast(), old_layout, new_layout).unwrap();
+        assert!(new_layout.size() <= slice.len());
+        assert_eq!(slice.len(), POOL.cache_size());
+        assert!(bytes(slice)[..new_layout.size()].iter().all(|&b| b == 0xa5));
+
+        // Shrinking to 0 byte deallocates the memory.
+        let zero = Layout::from_size_align(0, 8).unwrap();
+        let slice = alloc.shrink(slice.cast(), new_layout, zero).unwrap();
+        assert_eq!(0, slice.len());
+        assert_eq!(0, POOL.cache_size());
+    }
+}
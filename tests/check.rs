@@ -0,0 +1,205 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for feature `check` .
+//!
+//! Each test runs itself in a child process because an invalid pointer aborts the process.
+
+#![cfg(feature = "check")]
+
+use mouse_cache_alloc::{CPoolAlloc, CachePool};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::process::Command;
+
+const CHILD: &str = "MOUSE_CACHE_ALLOC_CHECK_CHILD";
+
+/// Runs `f` in a child process if this is the parent, or calls `f` if this is the child.
+/// The parent asserts that the child aborts with a message containing `message` .
+fn expect_abort<F>(name: &str, message: &str, f: F)
+where
+    F: FnOnce(),
+{
+    if env::var_os(CHILD).is_some() {
+        f();
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains(message), "{}", stderr);
+}
+
+static POOL: CachePool = CachePool::new();
+static OTHER: CachePool = CachePool::new();
+
+fn layout() -> Layout {
+    Layout::from_size_align(100, 8).unwrap()
+}
+
+#[test]
+fn valid() {
+    let alloc = CPoolAlloc::new(&POOL);
+
+    unsafe {
+        let ptr = alloc.alloc(layout());
+        let ptr = alloc.realloc(ptr, layout(), 200);
+        alloc.dealloc(ptr, Layout::from_size_align(200, 8).unwrap());
+
+        // The address can be reused after deallocated.
+        let ptr = alloc.alloc(layout());
+        alloc.dealloc(ptr, layout());
+    }
+}
+
+#[test]
+fn foreign_dealloc() {
+    expect_abort(
+        "foreign_dealloc",
+        "not allocated by this allocator",
+        || unsafe {
+            let ptr = System.alloc(layout());
+            CPoolAlloc::new(&POOL).dealloc(ptr, layout());
+        },
+    );
+}
+
+#[test]
+fn double_dealloc() {
+    expect_abort("double_dealloc", "double free", || unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout());
+        alloc.dealloc(ptr, layout());
+        alloc.dealloc(ptr, layout());
+    });
+}
+
+#[test]
+fn realloc_after_dealloc() {
+    expect_abort("realloc_after_dealloc", "invalid realloc", || unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout());
+        alloc.dealloc(ptr, layout());
+        alloc.realloc(ptr, layout(), 200);
+    });
+}
+
+#[test]
+fn other_pool() {
+    expect_abort("other_pool", "another pool", || unsafe {
+        let ptr = CPoolAlloc::new(&OTHER).alloc(layout());
+        CPoolAlloc::new(&POOL).dealloc(ptr, layout());
+    });
+}
+
+#[test]
+fn wrong_layout() {
+    expect_abort("wrong_layout", "allocated with size: 100", || unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout());
+        alloc.dealloc(ptr, Layout::from_size_align(50, 8).unwrap());
+    });
+}
+
+/// Returns the usable size of `ptr` , which is charged to `POOL` .
+fn usable_size(ptr: *mut u8) -> usize {
+    POOL.live_allocations()
+        .iter()
+        .find(|live| live.address == ptr as usize)
+        .unwrap()
+        .charged
+}
+
+#[test]
+fn fitting_layout() {
+    let alloc = CPoolAlloc::new(&POOL);
+
+    unsafe {
+        // Any size between the requested size and the usable size fits.
+        let ptr = alloc.alloc(layout());
+        let usable = usable_size(ptr);
+        assert!(layout().size() <= usable);
+        alloc.dealloc(ptr, Layout::from_size_align(usable, 8).unwrap());
+
+        let ptr = alloc.alloc(layout());
+        let usable = usable_size(ptr);
+        let fitting = Layout::from_size_align((layout().size() + usable) / 2, 8).unwrap();
+        let ptr = alloc.realloc(ptr, fitting, 200);
+        alloc.dealloc(ptr, Layout::from_size_align(200, 8).unwrap());
+    }
+}
+
+#[test]
+fn too_large_layout() {
+    expect_abort("too_large_layout", "allocated with size: 100", || unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout());
+        let usable = usable_size(ptr);
+        alloc.dealloc(ptr, Layout::from_size_align(usable + 1, 8).unwrap());
+    });
+}
+
+#[test]
+fn wrong_align() {
+    expect_abort("wrong_align", "align: 8", || unsafe {
+        let alloc = CPoolAlloc::new(&POOL);
+        let ptr = alloc.alloc(layout());
+        alloc.dealloc(ptr, Layout::from_size_align(100, 16).unwrap());
+    });
+}
+
+#[test]
+fn concurrent_realloc() {
+    static POOL: CachePool = CachePool::new();
+
+    // The address released by `realloc` of a thread can be allocated by another thread at once.
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            std::thread::spawn(|| unsafe {
+                let alloc = CPoolAlloc::new(&POOL);
+                for _ in 0..10_000 {
+                    let ptr = alloc.alloc(Layout::from_size_align(24, 8).unwrap());
+                    let ptr = alloc.realloc(ptr, Layout::from_size_align(24, 8).unwrap(), 48);
+                    alloc.dealloc(ptr, Layout::from_size_align(48, 8).unwrap());
+                }
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    assert_eq!(0, POOL.registered_size());
+}
@@ -0,0 +1,63 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for feature `check` with `Counted` as the global allocator.
+
+#![cfg(feature = "check")]
+
+use mouse_cache_alloc::{set_registry_backtrace, CachePool, Counted};
+use std::alloc::System;
+
+static POOL: CachePool = CachePool::new();
+
+#[global_allocator]
+static GLOBAL: Counted<System> = Counted::new(System, &POOL);
+
+#[test]
+fn unregistered() {
+    // The backtraces are allocated while the registry is being updated, so they are not
+    // registered.
+    set_registry_backtrace(true);
+    let boxed = Box::new(42);
+    let lives = POOL.live_allocations();
+    set_registry_backtrace(false);
+
+    let live = lives
+        .iter()
+        .find(|live| live.address == &*boxed as *const i32 as usize)
+        .unwrap();
+    assert!(live.backtrace.is_some());
+
+    // Dropping `lives` releases the last reference to the backtrace after `boxed` is
+    // unregistered.
+    drop(boxed);
+    drop(lives);
+}
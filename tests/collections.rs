@@ -0,0 +1,450 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of `CacheBox` , `CacheVec` , and `CacheString` .
+
+use mouse_cache_alloc::{CPoolAlloc, CacheBox, CachePool, CacheString, CacheVec, TryReserveError};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Element counting how many times it is dropped.
+#[derive(Clone)]
+struct Counter {
+    value: usize,
+    drops: Rc<Cell<usize>>,
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        self.drops.set(self.drops.get() + 1);
+    }
+}
+
+/// Returns `CacheVec` of `len` `Counter` s charged to `pool` .
+fn counters(
+    pool: &'static CachePool,
+    len: usize,
+    drops: &Rc<Cell<usize>>,
+) -> CacheVec<Counter, CPoolAlloc> {
+    let mut ret = CacheVec::new_in(CPoolAlloc::new(pool));
+    for value in 0..len {
+        ret.push(Counter {
+            value,
+            drops: drops.clone(),
+        });
+    }
+    ret
+}
+
+/// Returns the values of `v` .
+fn values(v: &[Counter]) -> Vec<usize> {
+    v.iter().map(|c| c.value).collect()
+}
+
+#[test]
+fn vec_truncate_clear() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let mut v = counters(&POOL, 10, &drops);
+        assert!(10 * std::mem::size_of::<Counter>() <= POOL.cache_size());
+
+        v.truncate(20);
+        assert_eq!(0, drops.get());
+
+        v.truncate(7);
+        assert_eq!(3, drops.get());
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6], values(&v));
+
+        let capacity = v.capacity();
+        v.clear();
+        assert_eq!(10, drops.get());
+        assert!(v.is_empty());
+        assert_eq!(capacity, v.capacity());
+
+        v.extend(counters(&POOL, 5, &drops));
+        v.shrink_to_fit();
+        assert_eq!(5, v.capacity());
+    }
+
+    assert_eq!(15, drops.get());
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn vec_into_iter() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    let v = counters(&POOL, 10, &drops);
+    let collected: Vec<usize> = v.into_iter().map(|c| c.value).collect();
+    assert_eq!((0..10).collect::<Vec<_>>(), collected);
+    assert_eq!(10, drops.get());
+    assert_eq!(0, POOL.cache_size());
+
+    // Partial iteration from both the ends.
+    let mut iter = counters(&POOL, 10, &drops).into_iter();
+    assert_eq!(10, iter.len());
+    assert_eq!(0, iter.next().unwrap().value);
+    assert_eq!(9, iter.next_back().unwrap().value);
+    assert_eq!(12, drops.get());
+    assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], values(iter.as_slice()));
+
+    drop(iter);
+    assert_eq!(20, drops.get());
+    assert_eq!(0, POOL.cache_size());
+
+    // Empty
+    let v: CacheVec<Counter, _> = CacheVec::new_in(CPoolAlloc::new(&POOL));
+    assert!(v.into_iter().next().is_none());
+}
+
+#[test]
+fn vec_insert_remove() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let mut v = counters(&POOL, 5, &drops);
+
+        let c = Counter {
+            value: 10,
+            drops: drops.clone(),
+        };
+        v.insert(2, c.clone());
+        v.insert(0, c.clone());
+        v.insert(7, c);
+        assert_eq!(vec![10, 0, 1, 10, 2, 3, 4, 10], values(&v));
+        assert_eq!(0, drops.get());
+
+        assert_eq!(10, v.remove(0).value);
+        assert_eq!(2, v.remove(3).value);
+        assert_eq!(10, v.remove(5).value);
+        assert_eq!(1, v.swap_remove(1).value);
+        assert_eq!(vec![0, 4, 10, 3], values(&v));
+        assert_eq!(4, drops.get());
+
+        assert_eq!(3, v.pop().unwrap().value);
+        assert_eq!(5, drops.get());
+    }
+
+    assert_eq!(8, drops.get());
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+#[should_panic]
+fn vec_insert_out_of_bounds() {
+    let mut v: CacheVec<u8> = CacheVec::new();
+    v.insert(1, 0);
+}
+
+#[test]
+#[should_panic]
+fn vec_remove_out_of_bounds() {
+    let mut v: CacheVec<u8> = CacheVec::new();
+    v.push(0);
+    v.remove(1);
+}
+
+#[test]
+fn vec_retain() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let mut v = counters(&POOL, 10, &drops);
+        v.retain(|c| c.value % 3 == 0);
+        assert_eq!(vec![0, 3, 6, 9], values(&v));
+        assert_eq!(6, drops.get());
+
+        v.retain(|_| true);
+        assert_eq!(vec![0, 3, 6, 9], values(&v));
+        assert_eq!(6, drops.get());
+
+        v.retain(|_| false);
+        assert!(v.is_empty());
+        assert_eq!(10, drops.get());
+    }
+
+    assert_eq!(10, drops.get());
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn vec_drain() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let mut v = counters(&POOL, 10, &drops);
+
+        let drained: Vec<usize> = v.drain(2..5).map(|c| c.value).collect();
+        assert_eq!(vec![2, 3, 4], drained);
+        assert_eq!(vec![0, 1, 5, 6, 7, 8, 9], values(&v));
+        assert_eq!(3, drops.get());
+
+        // Not consumed elements are dropped as well.
+        let mut drain = v.drain(1..=4);
+        assert_eq!(1, drain.next().unwrap().value);
+        assert_eq!(7, drain.next_back().unwrap().value);
+        drop(drain);
+        assert_eq!(vec![0, 8, 9], values(&v));
+        assert_eq!(7, drops.get());
+
+        assert_eq!(0, v.drain(3..).count());
+        assert_eq!(0, v.drain(..0).count());
+        assert_eq!(vec![0, 8, 9], values(&v));
+
+        assert_eq!(3, v.drain(..).count());
+        assert!(v.is_empty());
+        assert_eq!(10, drops.get());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+#[should_panic]
+fn vec_drain_out_of_bounds() {
+    let mut v: CacheVec<u8> = CacheVec::new();
+    v.push(0);
+    v.drain(..2);
+}
+
+#[test]
+fn vec_clone() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let v = counters(&POOL, 10, &drops);
+        let before = POOL.cache_size();
+
+        let w = v.clone();
+        assert_eq!(values(&v), values(&w));
+        let after = POOL.cache_size();
+        assert!(before + 10 * std::mem::size_of::<Counter>() <= after);
+
+        drop(v);
+        assert_eq!(10, drops.get());
+        assert_eq!(after - before, POOL.cache_size());
+        assert_eq!((0..10).collect::<Vec<_>>(), values(&w));
+    }
+
+    assert_eq!(20, drops.get());
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn vec_zst() {
+    static POOL: CachePool = CachePool::new();
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Zst;
+
+    impl Drop for Zst {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let drops = || DROPS.load(Ordering::Relaxed);
+
+    {
+        let mut v = CacheVec::new_in(CPoolAlloc::new(&POOL));
+        assert_eq!(usize::MAX, v.capacity());
+
+        (0..100).for_each(|_| v.push(Zst));
+        v.insert(50, Zst);
+        assert_eq!(101, v.len());
+        assert_eq!(0, POOL.cache_size());
+
+        drop(v.remove(0));
+        v.truncate(90);
+        assert_eq!(11, drops());
+
+        assert_eq!(10, v.drain(..10).count());
+        assert_eq!(21, drops());
+
+        let mut iter = v.into_iter();
+        drop(iter.next());
+        drop(iter);
+        assert_eq!(101, drops());
+    }
+
+    let mut v: CacheVec<(), _> = CacheVec::new_in(CPoolAlloc::new(&POOL));
+    assert_eq!(Ok(()), v.try_reserve(usize::MAX));
+    v.push(());
+    assert_eq!(
+        Err(TryReserveError::CapacityOverflow),
+        v.try_reserve(usize::MAX)
+    );
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn vec_try_reserve() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(1024);
+
+    let mut v: CacheVec<u64, _> = CacheVec::new_in(CPoolAlloc::new(&POOL));
+    assert_eq!(Ok(()), v.try_reserve(16));
+    let capacity = v.capacity();
+    (0..capacity).for_each(|i| v.try_push(i as u64).unwrap());
+
+    // Fails without changing anything.
+    assert!(matches!(
+        v.try_reserve(1024),
+        Err(TryReserveError::AllocError(_))
+    ));
+    assert!(matches!(
+        v.try_reserve_exact(1024),
+        Err(TryReserveError::AllocError(_))
+    ));
+    assert_eq!(
+        Err(TryReserveError::CapacityOverflow),
+        v.try_reserve(usize::MAX)
+    );
+    assert_eq!(capacity, v.capacity());
+    assert!(v.iter().copied().eq(0..capacity as u64));
+
+    // `try_push` fails as well when the capacity gets so large.
+    while v.try_push(0).is_ok() {}
+    assert!(v.len() * 8 <= 1024);
+    assert!(POOL.cache_size() <= 1024);
+
+    drop(v);
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn string_utf8() {
+    static POOL: CachePool = CachePool::new();
+
+    {
+        let mut s = CacheString::new_in(CPoolAlloc::new(&POOL));
+        s.push_str("aé");
+        s.push('あ');
+        s.push('🦀');
+        assert_eq!("aéあ🦀", s);
+        assert_eq!(1 + 2 + 3 + 4, s.len());
+
+        assert_eq!(Some('🦀'), s.pop());
+        assert_eq!(Some('あ'), s.pop());
+        assert_eq!("aé", s);
+
+        s.push('あ');
+        s.truncate(3);
+        assert_eq!("aé", s);
+        s.truncate(1);
+        assert_eq!("a", s);
+
+        let t = s.clone();
+        s.clear();
+        assert_eq!(None, s.pop());
+        assert_eq!("a", t);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+#[should_panic]
+fn string_truncate_inside_char() {
+    let mut s: CacheString = CacheString::from("aé");
+    s.truncate(2);
+}
+
+#[test]
+fn string_try_push_str() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(1024);
+
+    let mut s = CacheString::new_in(CPoolAlloc::new(&POOL));
+    assert_eq!(Ok(()), s.try_push_str("foo"));
+
+    let long = "x".repeat(2048);
+    assert!(s.try_push_str(&long).is_err());
+    assert!(s.try_reserve(2048).is_err());
+    assert_eq!("foo", s);
+
+    drop(s);
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn cache_box() {
+    static POOL: CachePool = CachePool::new();
+    let drops = Rc::new(Cell::new(0));
+
+    {
+        let alloc = CPoolAlloc::new(&POOL);
+        let counter = Counter {
+            value: 1,
+            drops: drops.clone(),
+        };
+
+        let b = CacheBox::new_in(counter, alloc);
+        assert!(std::mem::size_of::<Counter>() <= POOL.cache_size());
+
+        let c = b.clone();
+        assert_eq!(1, c.value);
+        drop(b);
+        assert_eq!(1, drops.get());
+
+        let counter = CacheBox::into_inner(c);
+        assert_eq!(1, drops.get());
+        assert_eq!(0, POOL.cache_size());
+        drop(counter);
+        assert_eq!(2, drops.get());
+
+        // ZST is not charged.
+        let b = CacheBox::new_in((), alloc);
+        assert_eq!(0, POOL.cache_size());
+        drop(b);
+    }
+
+    POOL.set_limit(1024);
+    let alloc = CPoolAlloc::new(&POOL);
+    let counter = Counter {
+        value: 2,
+        drops: drops.clone(),
+    };
+    assert!(CacheBox::try_new_in([0_u8; 2048], alloc).is_err());
+    assert_eq!(2, CacheBox::try_new_in(counter, alloc).unwrap().value);
+    assert_eq!(3, drops.get());
+
+    assert_eq!(0, POOL.cache_size());
+}
@@ -0,0 +1,120 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of `CAlloc` and `CPoolAlloc` under feature `dont_dump` .
+
+#![cfg(feature = "dont_dump")]
+
+use mouse_cache_alloc::{register_shrinker, unregister_shrinker, CPoolAlloc, CachePool};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(not(feature = "layout_size"))]
+#[test]
+fn charge() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+    let page_size = mmap_allocator::page_size();
+
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let large = Layout::from_size_align(page_size + 1, 8).unwrap();
+
+    unsafe {
+        // The small allocations share the slab pages.
+        let ptr = alloc.alloc(small);
+        assert!(!ptr.is_null());
+        assert_eq!(128, POOL.cache_size());
+
+        // Each large allocation is mapped by itself and charged in whole pages.
+        let ptr_ = alloc.alloc(large);
+        assert!(!ptr_.is_null());
+        assert_eq!(0, ptr_ as usize % page_size);
+        assert_eq!(128 + 2 * page_size, POOL.cache_size());
+
+        alloc.dealloc(ptr, small);
+        alloc.dealloc(ptr_, large);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn over_aligned() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+    let align = 4 * mmap_allocator::page_size();
+    let layout = Layout::from_size_align(100, align).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % align);
+        assert!(100 <= POOL.cache_size());
+        ptr.write_bytes(0xa5, 100);
+
+        let ptr = alloc.realloc(ptr, layout, 200);
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % align);
+        (0..100).for_each(|i| assert_eq!(0xa5, *ptr.add(i)));
+
+        alloc.dealloc(ptr, Layout::from_size_align(200, align).unwrap());
+
+        let ptr = alloc.alloc_zeroed(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % align);
+        (0..100).for_each(|i| assert_eq!(0, *ptr.add(i)));
+        alloc.dealloc(ptr, layout);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn shrink_once() {
+    static POOL: CachePool = CachePool::new();
+    static CALLED: AtomicUsize = AtomicUsize::new(0);
+
+    fn shrinker(bytes: usize) -> usize {
+        CALLED.fetch_add(1, Ordering::Relaxed);
+        bytes
+    }
+
+    let alloc = CPoolAlloc::new(&POOL);
+    let id = register_shrinker(0, shrinker).unwrap();
+
+    // The pool has no limit, but `mmap` fails.
+    let layout = Layout::from_size_align(1 << 60, 8).unwrap();
+    unsafe { assert!(alloc.alloc(layout).is_null()) };
+
+    assert_eq!(1, CALLED.load(Ordering::Relaxed));
+    assert_eq!(0, POOL.cache_size());
+    assert!(unregister_shrinker(id));
+}
@@ -0,0 +1,137 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the guard pages of `CMmapAlloc` .
+//!
+//! Touching a guard page kills the process, so the tests touching them run themselves in a
+//! child process.
+
+#![cfg(feature = "guard_pages")]
+
+use mouse_cache_alloc::{CMmapAlloc, CachePool, GuardPages};
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+
+const CHILD: &str = "MOUSE_CACHE_ALLOC_GUARD_CHILD";
+
+/// Runs `f` in a child process if this is the parent, or calls `f` if this is the child.
+/// The parent asserts that the child is killed by `SIGSEGV` .
+fn expect_segv<F>(name: &str, f: F)
+where
+    F: FnOnce(),
+{
+    if env::var_os(CHILD).is_some() {
+        f();
+        return;
+    }
+
+    let status = Command::new(env::current_exe().unwrap())
+        .args([name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, "1")
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(libc::SIGSEGV), status.signal());
+}
+
+/// Returns the permissions of the mapping containing `address` in `/proc/self/maps` .
+#[cfg(target_os = "linux")]
+fn permissions(address: usize) -> String {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    maps.lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let start = usize::from_str_radix(start, 16).ok()?;
+            let end = usize::from_str_radix(end, 16).ok()?;
+            if start <= address && address < end {
+                fields.next().map(String::from)
+            } else {
+                None
+            }
+        })
+        .unwrap()
+}
+
+fn layout() -> Layout {
+    Layout::from_size_align(128, 8).unwrap()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn protection() {
+    static POOL: CachePool = CachePool::new();
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        let after = CMmapAlloc::with_pool(&POOL).guard_pages(GuardPages::After);
+        let ptr = after.alloc(layout());
+        assert!(!ptr.is_null());
+        let guard = ptr as usize + layout().size();
+        assert_eq!(0, guard % page_size);
+        assert!(permissions(ptr as usize).starts_with("rw"));
+        assert!(permissions(guard).starts_with("---"));
+        after.dealloc(ptr, layout());
+
+        let before = CMmapAlloc::with_pool(&POOL).guard_pages(GuardPages::Before);
+        let ptr = before.alloc(layout());
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % page_size);
+        assert!(permissions(ptr as usize).starts_with("rw"));
+        assert!(permissions(ptr as usize - 1).starts_with("---"));
+        before.dealloc(ptr, layout());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+    assert_eq!(0, POOL.guard_size());
+}
+
+#[test]
+fn overrun() {
+    expect_segv("overrun", || unsafe {
+        static POOL: CachePool = CachePool::new();
+        let alloc = CMmapAlloc::with_pool(&POOL).guard_pages(GuardPages::After);
+        let ptr = alloc.alloc(layout());
+        ptr.add(layout().size()).write_volatile(0);
+    });
+}
+
+#[test]
+fn underrun() {
+    expect_segv("underrun", || unsafe {
+        static POOL: CachePool = CachePool::new();
+        let alloc = CMmapAlloc::with_pool(&POOL).guard_pages(GuardPages::Before);
+        let ptr = alloc.alloc(layout());
+        ptr.sub(1).write_volatile(0);
+    });
+}
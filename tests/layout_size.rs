@@ -0,0 +1,83 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of feature `layout_size` .
+//!
+//! Run with environment variable `MOUSE_CACHE_ALLOC_GRANULE` as well to test a non-default
+//! granule. (See `pre-commit` .)
+
+#![cfg(feature = "layout_size")]
+
+use mouse_cache_alloc::{CPoolAlloc, CachePool, UsableSize, SIZE_GRANULE};
+use std::alloc::{GlobalAlloc, Layout};
+
+fn round(size: usize) -> usize {
+    size.div_ceil(SIZE_GRANULE) * SIZE_GRANULE
+}
+
+#[test]
+fn granule() {
+    match option_env!("MOUSE_CACHE_ALLOC_GRANULE") {
+        None => assert_eq!(1, SIZE_GRANULE),
+        Some(s) => assert_eq!(s.parse::<usize>().unwrap(), SIZE_GRANULE),
+    }
+}
+
+#[test]
+fn usable_size_is_charged() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+
+    for size in 1..=256 {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+
+        unsafe {
+            let ptr = alloc.alloc(layout);
+            let usable = alloc.usable_size(ptr, layout);
+            assert_eq!(round(size), usable);
+            assert_eq!(usable, POOL.cache_size());
+
+            // The whole usable size is available.
+            ptr.write_bytes(0xa5, usable);
+
+            let new_layout = Layout::from_size_align(size + 100, 1).unwrap();
+            let ptr = alloc.realloc(ptr, layout, new_layout.size());
+            let usable = alloc.usable_size(ptr, new_layout);
+            assert_eq!(round(size + 100), usable);
+            assert_eq!(usable, POOL.cache_size());
+            ptr.write_bytes(0xa5, usable);
+
+            alloc.dealloc(ptr, new_layout);
+        }
+
+        assert_eq!(0, POOL.cache_size());
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the cache limit.
+
+use mouse_cache_alloc::{CPoolAlloc, CachePool, Counted};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+const LIMIT: usize = 64 * 1024;
+
+#[test]
+fn try_increase_size() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(LIMIT);
+
+    assert!(POOL.try_increase_size(LIMIT - 1));
+    assert!(!POOL.try_increase_size(2));
+    assert_eq!(LIMIT - 1, POOL.cache_size());
+
+    // Reaching the limit exactly is allowed.
+    assert!(POOL.try_increase_size(1));
+    assert!(!POOL.try_increase_size(1));
+    assert!(!POOL.try_increase_size(usize::MAX));
+    assert_eq!(LIMIT, POOL.cache_size());
+
+    POOL.decrease_size(LIMIT);
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn alloc() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(LIMIT);
+
+    let alloc = CPoolAlloc::new(&POOL);
+    let small = Layout::from_size_align(LIMIT / 4, 8).unwrap();
+    let large = Layout::from_size_align(LIMIT, 8).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(small);
+        assert!(!ptr.is_null());
+        let before = POOL.cache_size();
+
+        assert!(alloc.alloc(large).is_null());
+        assert!(alloc.alloc_zeroed(large).is_null());
+        assert_eq!(before, POOL.cache_size());
+        assert_eq!(2, POOL.stats().failures);
+
+        alloc.dealloc(ptr, small);
+        let ptr = alloc.alloc_zeroed(small);
+        assert!(!ptr.is_null());
+        alloc.dealloc(ptr, small);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn realloc() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(LIMIT);
+
+    let alloc = Counted::new(System, &POOL);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+        ptr.write_bytes(0xa5, layout.size());
+
+        // Growing within the limit.
+        let ptr = alloc.realloc(ptr, layout, LIMIT / 2);
+        assert!(!ptr.is_null());
+        assert!(LIMIT / 2 <= POOL.cache_size());
+        assert!(POOL.cache_size() <= LIMIT);
+        let layout = Layout::from_size_align(LIMIT / 2, 8).unwrap();
+        (0..1024).for_each(|i| assert_eq!(0xa5, *ptr.add(i)));
+
+        // Shrinking never fails.
+        let ptr = alloc.realloc(ptr, layout, 512);
+        assert!(!ptr.is_null());
+        let layout = Layout::from_size_align(512, 8).unwrap();
+        (0..512).for_each(|i| assert_eq!(0xa5, *ptr.add(i)));
+
+        alloc.dealloc(ptr, layout);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn realloc_failure() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(LIMIT);
+
+    let alloc = CPoolAlloc::new(&POOL);
+    let layout = Layout::from_size_align(LIMIT / 2, 8).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+        ptr.write_bytes(0xa5, layout.size());
+        let before = POOL.cache_size();
+
+        // Rolls back and leaves `ptr` valid.
+        assert!(alloc.realloc(ptr, layout, LIMIT).is_null());
+        assert_eq!(before, POOL.cache_size());
+        assert_eq!(1, POOL.stats().failures);
+        (0..layout.size()).for_each(|i| assert_eq!(0xa5, *ptr.add(i)));
+
+        // The limit is raised.
+        POOL.set_limit(4 * LIMIT);
+        let ptr = alloc.realloc(ptr, layout, LIMIT);
+        assert!(!ptr.is_null());
+        (0..layout.size()).for_each(|i| assert_eq!(0xa5, *ptr.add(i)));
+
+        alloc.dealloc(ptr, Layout::from_size_align(LIMIT, 8).unwrap());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn parent_limit() {
+    static PARENT: CachePool = CachePool::new();
+    static CHILD: CachePool = CachePool::with_parent(&PARENT);
+    PARENT.set_limit(LIMIT);
+
+    let alloc = CPoolAlloc::new(&CHILD);
+    let layout = Layout::from_size_align(LIMIT / 2 + 1, 8).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+
+        // The child has no limit of its own.
+        assert!(alloc.alloc(layout).is_null());
+        assert_eq!(CHILD.cache_size(), PARENT.cache_size());
+
+        alloc.dealloc(ptr, layout);
+    }
+
+    assert_eq!(0, CHILD.cache_size());
+    assert_eq!(0, PARENT.cache_size());
+}
@@ -0,0 +1,142 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of `LruCache` .
+
+use mouse_cache_alloc::{CachePool, LruCache, TryReserveError};
+
+#[test]
+fn budget() {
+    static POOL: CachePool = CachePool::new();
+
+    {
+        let mut cache: LruCache<u64, [u8; 256]> = LruCache::new(&POOL, 8 * 1024);
+        for i in 0..1000 {
+            cache.insert(i, [i as u8; 256]);
+            assert!(POOL.cache_size() <= 8 * 1024);
+        }
+
+        assert!(!cache.is_empty());
+        assert_eq!(Some(&[231; 256]), cache.peek(&999));
+        assert!(!cache.contains_key(&0));
+
+        // The most recently used entries survive.
+        let keys: Vec<u64> = cache.iter().map(|(&k, _)| k).collect();
+        let expected: Vec<u64> = (0..1000).rev().take(keys.len()).collect();
+        assert_eq!(expected, keys);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn pool_limit() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(5000);
+
+    {
+        // The budget is never reached; the limit of the pool is.
+        let mut cache: LruCache<u64, u64> = LruCache::new(&POOL, usize::MAX);
+        for i in 0..10_000 {
+            cache.insert(i, i);
+            assert!(POOL.cache_size() <= 5000);
+            assert_eq!(Some(&i), cache.peek(&i));
+        }
+
+        assert!(10 < cache.len());
+        let keys: Vec<u64> = cache.iter().map(|(&k, _)| k).collect();
+        let expected: Vec<u64> = (0..10_000).rev().take(keys.len()).collect();
+        assert_eq!(expected, keys);
+
+        for i in (0..10_000).rev().take(cache.len()) {
+            assert_eq!(Some(i), cache.remove(&i));
+        }
+        assert!(cache.is_empty());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn get_insert_remove() {
+    static POOL: CachePool = CachePool::new();
+
+    let mut cache: LruCache<u64, u64> = LruCache::new(&POOL, usize::MAX);
+    for i in 0..100 {
+        assert_eq!(None, cache.insert(i, i));
+    }
+    assert_eq!(100, cache.len());
+
+    // `get` marks the entry as the most recently used.
+    assert_eq!(Some(&0), cache.get(&0));
+    assert_eq!(Some(1), cache.insert(1, 101));
+    *cache.get_mut(&2).unwrap() += 100;
+    let keys: Vec<u64> = cache.iter().take(3).map(|(&k, _)| k).collect();
+    assert_eq!(vec![2, 1, 0], keys);
+
+    assert_eq!(Some((3, 3)), cache.pop_lru());
+    assert_eq!(Some(102), cache.remove(&2));
+    assert_eq!(None, cache.remove(&2));
+    assert_eq!(98, cache.len());
+
+    drop(cache);
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn too_large_entry() {
+    static POOL: CachePool = CachePool::new();
+    POOL.set_limit(1024);
+
+    {
+        let mut cache: LruCache<u64, [u8; 4096]> = LruCache::new(&POOL, usize::MAX);
+
+        // The entry never fits under the limit; it is rejected instead of aborting.
+        assert_eq!(None, cache.insert(0, [0; 4096]));
+        assert!(cache.is_empty());
+        assert!(matches!(
+            cache.try_insert(1, [1; 4096]),
+            Err(TryReserveError::AllocError(_))
+        ));
+        assert!(cache.is_empty());
+        assert!(POOL.cache_size() <= 1024);
+    }
+
+    {
+        // The bucket does not fit either.
+        let mut cache: LruCache<u64, u64> = LruCache::new(&POOL, usize::MAX);
+        POOL.set_limit(0);
+        assert!(cache.try_insert(0, 0).is_err());
+        assert!(cache.is_empty());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
@@ -0,0 +1,435 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of `CMmapAlloc` .
+
+use mouse_cache_alloc::{CMmapAlloc, CachePool, LockError};
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+
+// The mappings of a test could fill the address range after a mapping of another test, so
+// that `mremap` fails to grow it in place; the tests must not run concurrently.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Returns the layout of `pages` OS pages.
+fn pages(pages: usize) -> Layout {
+    Layout::from_size_align(pages * mmap_allocator::page_size(), 8).unwrap()
+}
+
+/// Writes `pattern` on each byte of `layout` from `ptr` .
+unsafe fn fill(ptr: *mut u8, layout: Layout, pattern: u8) {
+    ptr.write_bytes(pattern, layout.size());
+}
+
+/// Asserts that the first `len` bytes from `ptr` are `pattern` .
+unsafe fn assert_filled(ptr: *mut u8, len: usize, pattern: u8) {
+    (0..len).for_each(|i| assert_eq!(pattern, *ptr.add(i)));
+}
+
+#[test]
+fn slab_pressure_callback() {
+    static POOL: CachePool = CachePool::new();
+    static ALLOC: CMmapAlloc = CMmapAlloc::with_pool(&POOL).slab(true);
+    static CACHED: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(64, 8) };
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Releases the cached object to the same slab class under pressure.
+    fn on_high(_: usize) {
+        let ptr = CACHED.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !ptr.is_null() {
+            unsafe { ALLOC.dealloc(ptr, LAYOUT) };
+        }
+    }
+
+    let page_size = mmap_allocator::page_size();
+    POOL.set_watermarks(2 * page_size, 0);
+    POOL.set_pressure_callbacks(Some(on_high), None);
+
+    unsafe {
+        CACHED.store(ALLOC.alloc(LAYOUT), Ordering::Release);
+        assert_eq!(page_size, POOL.cache_size());
+
+        // Fills the first slab and maps the second one, which fires `on_high` .
+        let mut ptrs = Vec::new();
+        while !CACHED.load(Ordering::Acquire).is_null() {
+            let ptr = ALLOC.alloc(LAYOUT);
+            assert!(!ptr.is_null());
+            ptrs.push(ptr);
+        }
+        assert_eq!(2 * page_size, POOL.cache_size());
+
+        ptrs.into_iter().for_each(|ptr| ALLOC.dealloc(ptr, LAYOUT));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn remap_in_place() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL);
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        let ptr = alloc.alloc(pages(4));
+        assert!(!ptr.is_null());
+        fill(ptr, pages(4), 0xa5);
+
+        // Shrinking always keeps the address, and leaves the range after the mapping free.
+        let shrunk = alloc.realloc(ptr, pages(4), page_size);
+        assert_eq!(ptr, shrunk);
+        assert_eq!(page_size, POOL.cache_size());
+        assert_filled(ptr, page_size, 0xa5);
+
+        // Growing into the free range keeps the address.
+        let grown = alloc.realloc(ptr, pages(1), 3 * page_size);
+        assert_eq!(ptr, grown);
+        assert_eq!(3 * page_size, POOL.cache_size());
+        assert_filled(ptr, page_size, 0xa5);
+
+        // The new pages are zeroed.
+        assert_filled(ptr.add(page_size), 2 * page_size, 0);
+
+        // Resizing within the same pages does nothing.
+        let same = alloc.realloc(ptr, pages(3), 3 * page_size - 1);
+        assert_eq!(ptr, same);
+        assert_eq!(3 * page_size, POOL.cache_size());
+
+        alloc.dealloc(ptr, Layout::from_size_align(3 * page_size - 1, 8).unwrap());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn remap_move() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL);
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        // Shrinks a mapping to leave the page after it free.
+        let ptr = alloc.alloc(pages(3));
+        assert!(!ptr.is_null());
+        let ptr = alloc.realloc(ptr, pages(3), 2 * page_size);
+        fill(ptr, pages(2), 0xa5);
+
+        // Occupies the page so that the mapping cannot grow in place.
+        let blocker = libc::mmap(
+            ptr.add(2 * page_size) as *mut libc::c_void,
+            page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+            -1,
+            0,
+        );
+        assert_ne!(libc::MAP_FAILED, blocker);
+        let blocker = blocker as *mut u8;
+        fill(blocker, pages(1), 0x5a);
+
+        let moved = alloc.realloc(ptr, pages(2), 4 * page_size);
+        assert!(!moved.is_null());
+        assert_ne!(ptr, moved);
+        assert_eq!(4 * page_size, POOL.cache_size());
+        assert_filled(moved, 2 * page_size, 0xa5);
+        assert_filled(blocker, page_size, 0x5a);
+
+        alloc.dealloc(moved, pages(4));
+        libc::munmap(blocker as *mut libc::c_void, page_size);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn remap_limit() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL);
+    let page_size = mmap_allocator::page_size();
+    POOL.set_limit(2 * page_size);
+
+    unsafe {
+        let ptr = alloc.alloc(pages(2));
+        assert!(!ptr.is_null());
+        fill(ptr, pages(2), 0xa5);
+
+        // Rolls back and leaves `ptr` valid.
+        assert!(alloc.realloc(ptr, pages(2), 3 * page_size).is_null());
+        assert_eq!(2 * page_size, POOL.cache_size());
+        assert_eq!(1, POOL.stats().failures);
+        assert_filled(ptr, 2 * page_size, 0xa5);
+
+        // Shrinking is not limited.
+        let ptr = alloc.realloc(ptr, pages(2), page_size);
+        assert!(!ptr.is_null());
+        assert_eq!(page_size, POOL.cache_size());
+        assert_filled(ptr, page_size, 0xa5);
+
+        // Growing within the limit succeeds again.
+        let ptr = alloc.realloc(ptr, pages(1), 2 * page_size);
+        assert!(!ptr.is_null());
+        assert_eq!(2 * page_size, POOL.cache_size());
+        assert_filled(ptr, page_size, 0xa5);
+
+        alloc.dealloc(ptr, pages(2));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn purge_reclaim() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL);
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        let ptr = alloc.alloc(pages(2));
+        assert!(!ptr.is_null());
+        fill(ptr, pages(2), 0xa5);
+
+        let purged = alloc.purge(ptr, pages(2));
+        assert_eq!(ptr, purged.as_ptr());
+        assert_eq!(2 * page_size, purged.len());
+        assert_eq!(2 * page_size, POOL.purgeable_size());
+        assert_eq!(2 * page_size, POOL.cache_size());
+
+        // The sentinels are intact unless the kernel is short of memory.
+        assert!(purged.reclaim());
+        assert_eq!(0, POOL.purgeable_size());
+        assert_filled(ptr, 2 * page_size, 0xa5);
+
+        alloc.dealloc(ptr, pages(2));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn purge_discarded() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL);
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        let ptr = alloc.alloc(pages(2));
+        assert!(!ptr.is_null());
+        fill(ptr, pages(2), 0xa5);
+
+        let purged = alloc.purge(ptr, pages(2));
+        assert_eq!(2 * page_size, POOL.purgeable_size());
+
+        // Discards the second page as the kernel does under memory pressure.
+        let second = ptr.add(page_size);
+        assert_eq!(
+            0,
+            libc::madvise(second as *mut libc::c_void, page_size, libc::MADV_DONTNEED)
+        );
+
+        assert!(!purged.reclaim());
+        assert_eq!(0, POOL.purgeable_size());
+        assert_filled(ptr, page_size, 0xa5);
+        assert_filled(second, page_size, 0);
+
+        alloc.dealloc(ptr, pages(2));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn purge_drop() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL).slab(true);
+    let small = Layout::new::<u64>();
+
+    unsafe {
+        let ptr = alloc.alloc(pages(1));
+        assert!(!ptr.is_null());
+
+        // Dropping the token without reclaiming it.
+        let purged = alloc.purge(ptr, pages(1));
+        assert_eq!(purged.len(), POOL.purgeable_size());
+        drop(purged);
+        assert_eq!(0, POOL.purgeable_size());
+
+        // The slabs are never purged.
+        let slot = alloc.alloc(small);
+        assert!(!slot.is_null());
+        let purged = alloc.purge(slot, small);
+        assert!(purged.is_empty());
+        assert_eq!(0, POOL.purgeable_size());
+        assert!(purged.reclaim());
+
+        alloc.dealloc(slot, small);
+        alloc.dealloc(ptr, pages(1));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn slab_attributes() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let plain = CMmapAlloc::with_pool(&POOL).slab(true);
+    let locked = plain.mlock(true);
+    let dont_dump = plain.dont_dump(true);
+    let layout = Layout::new::<[u64; 8]>();
+    let page_size = mmap_allocator::page_size();
+    let page_of = |ptr: *mut u8| ptr as usize / page_size;
+
+    unsafe {
+        let ptr0 = locked.alloc(layout);
+        assert!(!ptr0.is_null());
+        assert_eq!(page_size, POOL.locked_size());
+
+        // The objects of the other attributes are placed on the other pages.
+        let ptr1 = plain.alloc(layout);
+        let ptr2 = dont_dump.alloc(layout);
+        assert!(!ptr1.is_null());
+        assert!(!ptr2.is_null());
+        assert_ne!(page_of(ptr0), page_of(ptr1));
+        assert_ne!(page_of(ptr0), page_of(ptr2));
+        assert_ne!(page_of(ptr1), page_of(ptr2));
+        assert_eq!(3 * page_size, POOL.cache_size());
+        assert_eq!(page_size, POOL.locked_size());
+
+        // The pages are accounted by their own attributes whichever instance releases them.
+        plain.dealloc(ptr0, layout);
+        assert_eq!(2 * page_size, POOL.cache_size());
+        assert_eq!(0, POOL.locked_size());
+
+        locked.dealloc(ptr1, layout);
+        assert_eq!(page_size, POOL.cache_size());
+        assert_eq!(0, POOL.locked_size());
+
+        plain.dealloc(ptr2, layout);
+    }
+
+    assert_eq!(0, POOL.cache_size());
+    assert_eq!(None, POOL.take_lock_error());
+}
+
+/// Calls `f` with `RLIMIT_MEMLOCK` of `bytes` .
+///
+/// `CAP_IPC_LOCK` ignores the limit, so it is dropped from the current thread while `f` runs.
+#[cfg(target_os = "linux")]
+unsafe fn with_memlock_limit<F>(bytes: usize, f: F)
+where
+    F: FnOnce(),
+{
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    const VERSION_3: u32 = 0x2008_0522;
+    const CAP_IPC_LOCK: u32 = 14;
+
+    let mut header = Header {
+        version: VERSION_3,
+        pid: 0,
+    };
+    let mut data = [Data::default(); 2];
+    assert_eq!(0, libc::syscall(libc::SYS_capget, &mut header, &mut data));
+    let saved = data;
+    data[0].effective &= !(1 << CAP_IPC_LOCK);
+    assert_eq!(0, libc::syscall(libc::SYS_capset, &mut header, &data));
+
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(0, libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlimit));
+    let saved_rlimit = rlimit;
+    rlimit.rlim_cur = bytes as libc::rlim_t;
+    assert_eq!(0, libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlimit));
+
+    f();
+
+    assert_eq!(0, libc::setrlimit(libc::RLIMIT_MEMLOCK, &saved_rlimit));
+    assert_eq!(0, libc::syscall(libc::SYS_capset, &mut header, &saved));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn remap_lock_error() {
+    static POOL: CachePool = CachePool::new();
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let alloc = CMmapAlloc::with_pool(&POOL).mlock(true);
+    let page_size = mmap_allocator::page_size();
+
+    unsafe {
+        let ptr = alloc.alloc(pages(1));
+        assert!(!ptr.is_null());
+        assert_eq!(page_size, POOL.locked_size());
+
+        with_memlock_limit(2 * page_size, || {
+            // Growing over the limit fails and leaves `ptr` valid.
+            assert!(alloc.realloc(ptr, pages(1), 8 * page_size).is_null());
+        });
+        assert_eq!(Some(LockError::Limit), POOL.take_lock_error());
+        assert_eq!(page_size, POOL.cache_size());
+        assert_eq!(page_size, POOL.locked_size());
+
+        let ptr = alloc.realloc(ptr, pages(1), 8 * page_size);
+        assert!(!ptr.is_null());
+        assert_eq!(8 * page_size, POOL.locked_size());
+
+        alloc.dealloc(ptr, pages(8));
+    }
+
+    assert_eq!(0, POOL.cache_size());
+    assert_eq!(0, POOL.locked_size());
+}
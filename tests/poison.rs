@@ -0,0 +1,89 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for feature `poison` .
+//!
+//! They are in a separate binary because the quarantine and the corruption handler are global.
+
+#![cfg(feature = "poison")]
+
+use mouse_cache_alloc::{flush_quarantine, set_corruption_handler, CPoolAlloc, CachePool};
+use mouse_cache_alloc::{Corruption, POISON};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Mutex;
+
+static DETECTED: Mutex<Vec<Corruption>> = Mutex::new(Vec::new());
+
+fn record(corruption: &Corruption) {
+    DETECTED.lock().unwrap().push(*corruption);
+}
+
+#[test]
+fn use_after_free() {
+    static POOL: CachePool = CachePool::new();
+    let alloc = CPoolAlloc::new(&POOL);
+    set_corruption_handler(Some(record));
+
+    let layout = Layout::from_size_align(100, 8).unwrap();
+
+    unsafe {
+        // Intact memory is released silently.
+        let ptr = alloc.alloc(layout);
+        ptr.write_bytes(1, layout.size());
+        alloc.dealloc(ptr, layout);
+        assert_eq!(*ptr.add(50), POISON);
+        assert_eq!(0, POOL.cache_size_exact());
+
+        // The old memory of `realloc` is quarantined as well.
+        let ptr = alloc.alloc(layout);
+        ptr.write_bytes(7, layout.size());
+        let ptr_ = alloc.realloc(ptr, layout, 1000);
+        assert_ne!(ptr, ptr_);
+        assert!((0..100).all(|i| *ptr_.add(i) == 7));
+        assert!((0..100).all(|i| *ptr.add(i) == POISON));
+        alloc.dealloc(ptr_, Layout::from_size_align(1000, 8).unwrap());
+        assert_eq!(0, POOL.cache_size_exact());
+
+        // The memory is still in the quarantine, so writing to it is detected.
+        let ptr = alloc.alloc(layout);
+        alloc.dealloc(ptr, layout);
+        *ptr.add(42) = 0;
+
+        flush_quarantine();
+        let detected = DETECTED.lock().unwrap();
+        assert_eq!(1, detected.len());
+        assert_eq!(ptr as usize, detected[0].address);
+        assert_eq!(layout, detected[0].layout);
+        assert_eq!(42, detected[0].offset);
+    }
+
+    set_corruption_handler(None);
+}
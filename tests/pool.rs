@@ -0,0 +1,276 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of `CachePool` .
+
+use mouse_cache_alloc::{CPoolAlloc, CachePool};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[test]
+fn parent_limit_realloc() {
+    static PARENT: CachePool = CachePool::new();
+    static CHILD: CachePool = CachePool::with_parent(&PARENT);
+    PARENT.set_limit(64 * 1024);
+
+    let alloc = CPoolAlloc::new(&CHILD);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+        ptr.write(0xa5);
+
+        let before = CHILD.cache_size();
+        assert!(alloc.realloc(ptr, layout, 128 * 1024).is_null());
+        assert_eq!(before, CHILD.cache_size());
+        assert_eq!(before, PARENT.cache_size());
+
+        let ptr = alloc.realloc(ptr, layout, 32 * 1024);
+        assert!(!ptr.is_null());
+        assert_eq!(0xa5, ptr.read());
+        assert!(PARENT.cache_size() <= PARENT.limit());
+
+        alloc.dealloc(ptr, Layout::from_size_align(32 * 1024, 8).unwrap());
+    }
+
+    assert_eq!(0, CHILD.cache_size());
+    assert_eq!(0, PARENT.cache_size());
+}
+
+#[test]
+fn watermarks() {
+    static POOL: CachePool = CachePool::new();
+    static HIGH: AtomicUsize = AtomicUsize::new(0);
+    static LOW: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_high(size: usize) {
+        assert!(1000 <= size);
+        HIGH.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_low(size: usize) {
+        assert!(size <= 100);
+        LOW.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts() -> (usize, usize) {
+        (HIGH.load(Ordering::Relaxed), LOW.load(Ordering::Relaxed))
+    }
+
+    POOL.set_watermarks(1000, 100);
+    POOL.set_pressure_callbacks(Some(on_high), Some(on_low));
+
+    POOL.increase_size(999);
+    assert_eq!((0, 0), counts());
+    assert!(!POOL.is_under_pressure());
+
+    // Reaching the high watermark fires `on_high` only once.
+    POOL.increase_size(1);
+    POOL.increase_size(1000);
+    assert!(POOL.try_increase_size(1000));
+    assert_eq!((1, 0), counts());
+    assert!(POOL.is_under_pressure());
+
+    // Hysteresis; nothing happens between the watermarks.
+    POOL.decrease_size(2800);
+    POOL.increase_size(1000);
+    POOL.decrease_size(1000);
+    assert_eq!((1, 0), counts());
+    assert!(POOL.is_under_pressure());
+
+    // Falling to the low watermark fires `on_low` only once.
+    POOL.decrease_size(99);
+    POOL.decrease_size(1);
+    assert_eq!((1, 1), counts());
+    assert!(!POOL.is_under_pressure());
+
+    POOL.increase_size(1000);
+    POOL.decrease_size(1000);
+    assert_eq!((2, 2), counts());
+
+    // `None` unregisters the callbacks, while the state is still tracked.
+    POOL.set_pressure_callbacks(None, None);
+    POOL.increase_size(1000);
+    assert!(POOL.is_under_pressure());
+    POOL.decrease_size(1000);
+    assert!(!POOL.is_under_pressure());
+    assert_eq!((2, 2), counts());
+
+    POOL.decrease_size(100);
+    assert_eq!(0, POOL.cache_size());
+}
+
+#[test]
+fn watermarks_concurrent() {
+    static POOL: CachePool = CachePool::new();
+    static HIGH: AtomicUsize = AtomicUsize::new(0);
+    static LOW: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_high(_: usize) {
+        HIGH.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_low(_: usize) {
+        LOW.fetch_add(1, Ordering::Relaxed);
+    }
+
+    POOL.set_watermarks(1000, 1000);
+    POOL.set_pressure_callbacks(Some(on_high), Some(on_low));
+
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..100_000 {
+                    POOL.increase_size(600);
+                    POOL.decrease_size(600);
+                }
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    // The size is 0, so the last transition must be `on_low` .
+    assert_eq!(0, POOL.cache_size());
+    assert!(!POOL.is_under_pressure());
+    assert_eq!(HIGH.load(Ordering::Relaxed), LOW.load(Ordering::Relaxed));
+}
+
+#[test]
+fn watermarks_equal() {
+    static POOL: CachePool = CachePool::new();
+    static HIGH: AtomicUsize = AtomicUsize::new(0);
+    static LOW: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_high(_: usize) {
+        HIGH.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_low(_: usize) {
+        LOW.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts() -> (usize, usize) {
+        (HIGH.load(Ordering::Relaxed), LOW.load(Ordering::Relaxed))
+    }
+
+    POOL.set_watermarks(100, 100);
+    POOL.set_pressure_callbacks(Some(on_high), Some(on_low));
+
+    // The size just on the watermarks is high after an increase, and low after a decrease.
+    POOL.increase_size(100);
+    assert_eq!((1, 0), counts());
+    assert!(POOL.is_under_pressure());
+
+    POOL.increase_size(1);
+    POOL.decrease_size(1);
+    assert_eq!((1, 1), counts());
+    assert!(!POOL.is_under_pressure());
+
+    POOL.decrease_size(100);
+    assert_eq!((1, 1), counts());
+
+    // The size lands on the watermarks concurrently.
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..100_000 {
+                    POOL.increase_size(50);
+                    POOL.decrease_size(50);
+                }
+            })
+        })
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    assert_eq!(0, POOL.cache_size());
+    assert!(!POOL.is_under_pressure());
+    assert_eq!(HIGH.load(Ordering::Relaxed), LOW.load(Ordering::Relaxed));
+}
+
+#[test]
+fn parent_stats() {
+    static PARENT: CachePool = CachePool::new();
+    static CHILD_A: CachePool = CachePool::with_parent(&PARENT);
+    static CHILD_B: CachePool = CachePool::with_parent(&PARENT);
+
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let large = Layout::from_size_align(1000, 8).unwrap();
+
+    unsafe {
+        let a = CPoolAlloc::new(&CHILD_A).alloc(small);
+        let b = CPoolAlloc::new(&CHILD_B).alloc(small);
+        let b = CPoolAlloc::new(&CHILD_B).realloc(b, small, large.size());
+        let p = CPoolAlloc::new(&PARENT).alloc(small);
+
+        let stats = CHILD_A.stats();
+        assert_eq!(
+            (1, 0, 1),
+            (
+                stats.allocations,
+                stats.reallocations,
+                stats.live_allocations
+            )
+        );
+        assert_eq!(100, stats.requested_bytes);
+
+        let stats = CHILD_B.stats();
+        assert_eq!(
+            (1, 1, 1),
+            (
+                stats.allocations,
+                stats.reallocations,
+                stats.live_allocations
+            )
+        );
+        assert_eq!(1000, stats.requested_bytes);
+
+        // The parent includes the children.
+        let stats = PARENT.stats();
+        assert_eq!(3, stats.allocations);
+        assert_eq!(1, stats.reallocations);
+        assert_eq!(3, stats.live_allocations);
+        assert_eq!(1200, stats.requested_bytes);
+        assert_eq!(PARENT.cache_size_exact(), stats.charged_bytes);
+
+        CPoolAlloc::new(&CHILD_A).dealloc(a, small);
+        CPoolAlloc::new(&CHILD_B).dealloc(b, large);
+        CPoolAlloc::new(&PARENT).dealloc(p, small);
+    }
+
+    let stats = PARENT.stats();
+    assert_eq!(3, stats.deallocations);
+    assert_eq!(0, stats.live_allocations);
+    assert_eq!(0, stats.requested_bytes);
+    assert_eq!(0, stats.charged_bytes);
+    assert_eq!(1, CHILD_A.stats().deallocations);
+}
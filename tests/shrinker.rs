@@ -0,0 +1,199 @@
+// Copyright 2020, 2021 Shin Yoshida
+//
+// "LGPL-3.0-or-later OR Apache-2.0"
+//
+// This is part of mouse-cache-alloc
+//
+//  mouse-cache-alloc is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  mouse-cache-alloc is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public License
+//  along with mouse-cache-alloc.  If not, see <http://www.gnu.org/licenses/>.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the shrinkers.
+
+use mouse_cache_alloc::{
+    register_shrinker, shrink, unregister_shrinker, CPoolAlloc, CachePool, MAX_SHRINKERS,
+};
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// The shrinkers are global; the tests must not run concurrently.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn register_unregister() {
+    static CALLED: AtomicUsize = AtomicUsize::new(0);
+    let _guard = LOCK.lock().unwrap();
+
+    fn shrinker(bytes: usize) -> usize {
+        CALLED.fetch_add(1, Ordering::Relaxed);
+        bytes
+    }
+
+    assert_eq!(0, shrink(100));
+
+    let id = register_shrinker(0, shrinker).unwrap();
+    assert_eq!(100, shrink(100));
+    assert_eq!(1, CALLED.load(Ordering::Relaxed));
+
+    // No shrinker is called for 0 byte.
+    assert_eq!(0, shrink(0));
+    assert_eq!(1, CALLED.load(Ordering::Relaxed));
+
+    assert!(unregister_shrinker(id));
+    assert_eq!(0, shrink(100));
+    assert_eq!(1, CALLED.load(Ordering::Relaxed));
+}
+
+#[test]
+fn priority() {
+    static ORDER: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+    let _guard = LOCK.lock().unwrap();
+
+    fn low(_: usize) -> usize {
+        ORDER.lock().unwrap().push(-1);
+        10
+    }
+
+    fn high(_: usize) -> usize {
+        ORDER.lock().unwrap().push(1);
+        10
+    }
+
+    let ids = [
+        register_shrinker(-1, low).unwrap(),
+        register_shrinker(1, high).unwrap(),
+    ];
+
+    // Stops when enough memory is released.
+    assert_eq!(10, shrink(10));
+    assert_eq!(vec![1], *ORDER.lock().unwrap());
+
+    assert_eq!(20, shrink(15));
+    assert_eq!(vec![1, 1, -1], *ORDER.lock().unwrap());
+
+    ids.iter().for_each(|&id| assert!(unregister_shrinker(id)));
+}
+
+#[test]
+fn stale_id() {
+    let _guard = LOCK.lock().unwrap();
+
+    fn first(_: usize) -> usize {
+        1
+    }
+
+    fn second(_: usize) -> usize {
+        2
+    }
+
+    let stale = register_shrinker(0, first).unwrap();
+    assert!(unregister_shrinker(stale));
+
+    // The slot is reused.
+    let id = register_shrinker(0, second).unwrap();
+    assert_ne!(stale, id);
+
+    assert!(!unregister_shrinker(stale));
+    assert_eq!(2, shrink(1));
+
+    assert!(unregister_shrinker(id));
+    assert!(!unregister_shrinker(id));
+    assert_eq!(0, shrink(1));
+}
+
+#[test]
+fn exhaustion() {
+    let _guard = LOCK.lock().unwrap();
+
+    fn shrinker(_: usize) -> usize {
+        1
+    }
+
+    let ids: Vec<_> = (0..MAX_SHRINKERS)
+        .map(|_| register_shrinker(0, shrinker).unwrap())
+        .collect();
+    assert!(register_shrinker(0, shrinker).is_none());
+    assert_eq!(MAX_SHRINKERS, shrink(usize::MAX));
+
+    assert!(unregister_shrinker(ids[0]));
+    let id = register_shrinker(0, shrinker).unwrap();
+    assert!(register_shrinker(0, shrinker).is_none());
+
+    assert!(unregister_shrinker(id));
+    ids[1..]
+        .iter()
+        .for_each(|&id| assert!(unregister_shrinker(id)));
+    assert_eq!(0, shrink(usize::MAX));
+}
+
+#[test]
+fn retry_with_shrink() {
+    static POOL: CachePool = CachePool::new();
+    static CACHED: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+    let _guard = LOCK.lock().unwrap();
+
+    fn layout() -> Layout {
+        Layout::from_size_align(32 * 1024, 8).unwrap()
+    }
+
+    // Releases the cached memory synchronously.
+    fn shrinker(_: usize) -> usize {
+        let ptr = CACHED.swap(std::ptr::null_mut(), Ordering::Relaxed);
+        if ptr.is_null() {
+            return 0;
+        }
+
+        let before = POOL.cache_size();
+        unsafe { CPoolAlloc::new(&POOL).dealloc(ptr, layout()) };
+        before - POOL.cache_size()
+    }
+
+    POOL.set_limit(48 * 1024);
+    let alloc = CPoolAlloc::new(&POOL);
+
+    unsafe {
+        let ptr = alloc.alloc(layout());
+        assert!(!ptr.is_null());
+        CACHED.store(ptr, Ordering::Relaxed);
+
+        // Fails without the shrinker.
+        assert!(alloc.alloc(layout()).is_null());
+        assert_eq!(ptr, CACHED.load(Ordering::Relaxed));
+
+        // Succeeds after the shrinker releases the cache.
+        let id = register_shrinker(0, shrinker).unwrap();
+        let ptr = alloc.alloc(layout());
+        assert!(!ptr.is_null());
+        assert!(CACHED.load(Ordering::Relaxed).is_null());
+
+        // Fails again if the shrinker releases nothing.
+        assert!(alloc.alloc(layout()).is_null());
+
+        assert!(unregister_shrinker(id));
+        alloc.dealloc(ptr, layout());
+    }
+
+    assert_eq!(0, POOL.cache_size());
+}